@@ -0,0 +1,111 @@
+#import bevy_core_pipeline::fullscreen_vertex_shader::FullscreenVertexOutput
+#import bevy_pbr::{
+    atmosphere::{
+        bindings::view,
+        functions::{
+            uv_to_ndc,
+            uv_to_ray_direction,
+        }
+    },
+    mesh_view_bindings::globals,
+}
+
+@group(0) @binding(0) var depth_texture: texture_depth_multisampled_2d;
+@group(0) @binding(1) var texture: texture_2d<f32>;
+@group(0) @binding(2) var texture_sampler: sampler;
+@group(0) @binding(32) var<uniform> environment: common::Environment;
+
+const steps = 24;
+const light_steps = 4;
+// Clouds fade out towards this distance instead of ending abruptly
+const max_dist = 20000.0;
+// Forward scattering, giving the bright edges of clouds in front of the sun (Henyey-Greenstein)
+const anisotropy = 0.3;
+// Light reaching the clouds from the rest of the sky, relative to the sun or moon
+const ambient = 0.03;
+// Below this the clouds behind don't show anymore
+const min_transmittance = 0.01;
+
+@fragment
+fn main(in: FullscreenVertexOutput) -> @location(0) vec4<f32> {
+    let in_color = textureSample(texture, texture_sampler, in.uv);
+
+    let ray_dir = uv_to_ray_direction(in.uv).xyz;
+    let pos = view.world_position;
+
+    // Nothing drawn means sky
+    let depth = textureLoad(depth_texture, vec2<i32>(in.position.xy), 0);
+    var scene_dist = max_dist;
+    if depth > 0.0 {
+        scene_dist = min(ndc_to_camera_dist(vec3(uv_to_ndc(in.uv), depth)), max_dist);
+    }
+
+    // Part of the ray inside the cloud layer, in front of the scene
+    let dir_y = select(ray_dir.y, 1e-6, abs(ray_dir.y) < 1e-6);
+    let t_bottom = (environment.cloud_bottom - pos.y) / dir_y;
+    let t_top = (environment.cloud_top - pos.y) / dir_y;
+    let t_start = max(min(t_bottom, t_top), 0.0);
+    let t_end = min(max(t_bottom, t_top), scene_dist);
+    if t_end <= t_start {
+        return in_color;
+    }
+
+    let time = globals.time;
+    let cloud_height = (environment.cloud_bottom + environment.cloud_top) * 0.5;
+    let sun = common::light_transmittance(cloud_height, environment.sun_dir) * common::sun_intensity;
+    let moon = common::light_transmittance(cloud_height, environment.moon_dir)
+        * common::moon_intensity(environment.moon_illumination);
+    let sun_phase = phase(dot(ray_dir, environment.sun_dir));
+    let moon_phase = phase(dot(ray_dir, environment.moon_dir));
+
+    // Offset each pixel's samples to trade banding for noise
+    let jitter = f32(common::hash(u32(in.position.x) ^ common::hash(u32(in.position.y)))) / 4294967296.0;
+    let step = (t_end - t_start) / f32(steps);
+    var transmittance = 1.0;
+    var light = vec3(0.0);
+    var cloud_dist = t_end;
+    for (var i = 0; i < steps; i++) {
+        let t = t_start + (f32(i) + jitter) * step;
+        let sample_pos = pos + ray_dir * t;
+        let density = common::cloud_density(sample_pos, time, 4, environment);
+        if density <= 0.0 {
+            continue;
+        }
+        cloud_dist = min(cloud_dist, t);
+        let sun_shadow = common::cloud_transmittance(sample_pos, environment.sun_dir, time, light_steps, environment);
+        let moon_shadow = common::cloud_transmittance(sample_pos, environment.moon_dir, time, light_steps, environment);
+        let in_light = sun * (sun_shadow * sun_phase + ambient)
+            + moon * (moon_shadow * moon_phase + ambient)
+            + common::airglow;
+        let sample_transmittance = exp(-density * step);
+        light += transmittance * in_light * (1.0 - sample_transmittance);
+        transmittance *= sample_transmittance;
+        if transmittance < min_transmittance {
+            break;
+        }
+    }
+
+    let coverage = (1.0 - transmittance) * (1.0 - smoothstep(max_dist * 0.5, max_dist, cloud_dist));
+    if coverage <= 0.0 {
+        return in_color;
+    }
+    var cloud_color = common::aerial_perspective(
+        common::expose(light / (1.0 - transmittance)),
+        pos.y,
+        ray_dir,
+        cloud_dist,
+        environment,
+    );
+    cloud_color = common::fog(cloud_color, min(cloud_dist, common::fog_dist_to_sky(ray_dir)), environment);
+    return vec4(mix(in_color.rgb, cloud_color, coverage), 1.0);
+}
+
+fn phase(cos_theta: f32) -> f32 {
+    const g = anisotropy;
+    return (1.0 - g * g) / (4.0 * common::PI * pow(1.0 + g * g - 2.0 * g * cos_theta, 1.5));
+}
+
+fn ndc_to_camera_dist(ndc: vec3<f32>) -> f32 {
+    let view_pos = view.view_from_clip * vec4(ndc, 1.0);
+    return length(view_pos.xyz / view_pos.w);
+}
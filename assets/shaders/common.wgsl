@@ -1,29 +1,332 @@
 #define_import_path common
 
-const grass_color = vec3(0.1, 0.4, 0.0);
+#import noisy_bevy::fbm_simplex_3d
 
-// Returns the vector pointing to the sun
-fn sun_dir(time: f32) -> vec3<f32> {
-    const day_length = 100.0;
-    return normalize(vec3(
-        sin(time / day_length),
-        cos(time / day_length),
-        0.5,
-    ));
+const PI = 3.14159265358979;
+
+// `EnvironmentUniform` in Rust code
+struct Environment {
+    // Points to the sun
+    sun_dir: vec3<f32>,
+    seed: u32,
+    // Points to the moon
+    moon_dir: vec3<f32>,
+    // Illuminated fraction of the moon's disc
+    moon_illumination: f32,
+    // Into the frame of the celestial sphere, Z pointing to the celestial north pole
+    equatorial_from_world: mat3x3<f32>,
+    // See `CloudSettings` in Rust code
+    cloud_bottom: f32,
+    cloud_top: f32,
+    cloud_coverage: f32,
+    cloud_density: f32,
+    cloud_scale: f32,
+    wind: vec2<f32>,
+    // See `WeatherConditions` in Rust code
+    fog_density: f32,
+    wave_strength: f32,
+    // See `TerrainShading` in Rust code
+    grass_color: vec3<f32>,
+    // `light_transmittance` of the sun- and moonlight at the camera's height, the same for
+    // every pixel
+    sun_transmittance: vec3<f32>,
+    moon_transmittance: vec3<f32>,
+    // And at the ground, for the fog
+    ground_sun_transmittance: vec3<f32>,
+    ground_moon_transmittance: vec3<f32>,
+    // Of the main camera, also in the shadow and prepass views
+    camera_position: vec3<f32>,
 }
 
-// Relative to the sun's brightness
-const moon_brightness = 0.3;
+// PCG hash, keep in sync with `hash` in Rust code
+// https://www.jcgt.org/published/0009/03/02/
+fn hash(x: u32) -> u32 {
+    let state = x * 747796405u + 2891336453u;
+    let word = ((state >> ((state >> 28u) + 4u)) ^ state) * 277803737u;
+    return (word >> 22u) ^ word;
+}
+
+// Offset into the noise domain in [0, 1024)², picking a different region for each seed.
+// `salt` tells apart the patterns sharing one seed.
+// Keep in sync with `seed_offset` in Rust code.
+fn seed_offset(seed: u32, salt: u32) -> vec2<f32> {
+    let h = hash(seed ^ hash(salt));
+    return vec2(f32(h & 0xffffu), f32(h >> 16u)) / 64.0;
+}
 
-// TODO: improve
-fn moon_dir(sun_dir: vec3<f32>) -> vec3<f32> {
-    return vec3(-sun_dir.xy, sun_dir.z);
+// `simplex_noise_2d` along with its analytic derivatives, kept step by step like it for the
+// noise to match exactly. Keep in sync with `simplex_noise_2d_derivative` in Rust code.
+// Returns:
+// - x: noise
+// - yz: gradient
+fn simplex_noise_2d_derivative(v: vec2<f32>) -> vec3<f32> {
+    return simplex_noise_2d_derivatives(v).first;
 }
 
-fn sky_brightness(mapped_sun_height: f32, mapped_moon_height: f32) -> f32 {
-    return clamp(mapped_sun_height + mapped_moon_height * moon_brightness, 0.1, 1.0);
+// `simplex_noise_2d_derivative` along with the second derivatives
+struct NoiseDerivatives {
+    // Noise and gradient
+    first: vec3<f32>,
+    hessian: mat2x2<f32>,
+}
+
+// MIT License. © Ian McEwan, Stefan Gustavson, Munrocket, Johan Helsing
+// Keep in sync with `simplex_noise_2d_derivatives` in Rust code
+fn simplex_noise_2d_derivatives(v: vec2<f32>) -> NoiseDerivatives {
+    let C = vec4(
+        0.211324865405187, // (3.0 - sqrt(3.0)) / 6.0
+        0.366025403784439, // 0.5 * (sqrt(3.0) - 1.0)
+        -0.577350269189626, // -1.0 + 2.0 * C.x
+        0.024390243902439 // 1.0 / 41.0
+    );
+
+    var i = floor(v + dot(v, C.yy));
+    let x0 = v - i + dot(i, C.xx);
+    let i1 = select(vec2(0., 1.), vec2(1., 0.), x0.x > x0.y);
+    let x12 = x0.xyxy + C.xxzz - vec4(i1, 0., 0.);
+    let x1 = x12.xy;
+    let x2 = x12.zw;
+    i = i % vec2(289.);
+    let p = permute_3(permute_3(i.y + vec3(0., i1.y, 1.)) + i.x + vec3(0., i1.x, 1.));
+
+    // Falloff of each corner
+    let t = max(0.5 - vec3(dot(x0, x0), dot(x1, x1), dot(x2, x2)), vec3(0.));
+    let t2 = t * t;
+    let t4 = t2 * t2;
+
+    let x = 2. * fract(p * C.www) - 1.;
+    let h = abs(x) - 0.5;
+    let ox = floor(x + 0.5);
+    let a0 = x - ox;
+    let scale = 1.79284291400159 - 0.85373472095314 * (a0 * a0 + h * h);
+    let m = t4 * scale;
+    let g = vec3(a0.x * x0.x + h.x * x0.y, a0.yz * x12.xz + h.yz * x12.yw);
+
+    // Product rule over each corner's falloff t^4 and gradient ramp g, with d(t)/dv = -2 * x
+    let dt4 = -8. * t2 * t * scale * g;
+    let gradient = dt4.x * x0 + dt4.y * x1 + dt4.z * x2
+        + m.x * vec2(a0.x, h.x) + m.y * vec2(a0.y, h.y) + m.z * vec2(a0.z, h.z);
+
+    // Once more, per corner
+    let hessian = simplex_corner_hessian(x0, vec2(a0.x, h.x), t.x, g.x, scale.x)
+        + simplex_corner_hessian(x1, vec2(a0.y, h.y), t.y, g.y, scale.y)
+        + simplex_corner_hessian(x2, vec2(a0.z, h.z), t.z, g.z, scale.z);
+    return NoiseDerivatives(130. * vec3(dot(m, g), gradient), 130. * hessian);
+}
+
+fn simplex_corner_hessian(x: vec2<f32>, grad: vec2<f32>, t: f32, g: f32, scale: f32) -> mat2x2<f32> {
+    let t2 = t * t;
+    return (outer(x, x) * (48. * t2 * g)
+        - (outer(x, grad) + outer(grad, x) + mat2x2(g, 0., 0., g)) * (8. * t2 * t)) * scale;
+}
+
+fn outer(a: vec2<f32>, b: vec2<f32>) -> mat2x2<f32> {
+    return mat2x2(a * b.x, a * b.y);
+}
+
+fn permute_3(x: vec3<f32>) -> vec3<f32> {
+    return (((x * 34.) + 1.) * x) % vec3(289.);
+}
+
+// Relative to the sun's brightness, at full moon
+const moon_brightness = 0.3;
+
+fn sky_brightness(mapped_sun_height: f32, mapped_moon_height: f32, moon_illumination: f32) -> f32 {
+    return clamp(mapped_sun_height + mapped_moon_height * moon_brightness * moon_illumination, 0.1, 1.0);
 }
 
 fn map_sky_height(ray_dir_y: f32) -> f32 {
     return pow(smoothstep(0.0, 1.0, ray_dir_y), 0.3);
 }
+
+// Atmospheric scattering of sun- and moonlight, single scattering only. See
+// https://www.scratchapixel.com/lessons/procedural-generation-virtual-worlds/simulating-sky/simulating-colors-of-the-sky.html
+// Distances are in world units (meters), heights relative to the water level.
+
+const earth_radius = 6360e3;
+const atmosphere_radius = 6420e3;
+const rayleigh_scattering = vec3(5.8e-6, 13.5e-6, 33.1e-6);
+const rayleigh_scale_height = 8e3;
+const mie_scattering = 21e-6;
+const mie_extinction = 1.1 * mie_scattering;
+const mie_scale_height = 1.2e3;
+// Forward scattering of haze, giving the glow around the sun
+const mie_anisotropy = 0.76;
+const sun_intensity = 20.0;
+// Faint light of the night sky when neither sun nor moon are up
+const airglow = vec3(0.01, 0.015, 0.03);
+
+fn moon_intensity(moon_illumination: f32) -> f32 {
+    return sun_intensity * moon_brightness * moon_illumination;
+}
+
+fn rayleigh_phase(cos_theta: f32) -> f32 {
+    return 3.0 / (16.0 * PI) * (1.0 + cos_theta * cos_theta);
+}
+
+// Cornette-Shanks
+fn mie_phase(cos_theta: f32) -> f32 {
+    const g = mie_anisotropy;
+    let denom = (2.0 + g * g) * pow(1.0 + g * g - 2.0 * g * cos_theta, 1.5);
+    return 3.0 / (8.0 * PI) * (1.0 - g * g) * (1.0 + cos_theta * cos_theta) / denom;
+}
+
+// Rayleigh and Mie densities relative to the water level
+fn atmosphere_density(height: f32) -> vec2<f32> {
+    return exp(-max(height, 0.0) / vec2(rayleigh_scale_height, mie_scale_height));
+}
+
+// Per unit of distance, for the given densities or optical depths
+fn atmosphere_extinction(density: vec2<f32>) -> vec3<f32> {
+    return rayleigh_scattering * density.x + mie_extinction * density.y;
+}
+
+// Light scattered towards the viewer per unit of distance and incoming light
+fn atmosphere_scattering(density: vec2<f32>, cos_theta: f32) -> vec3<f32> {
+    return rayleigh_scattering * density.x * rayleigh_phase(cos_theta)
+        + mie_scattering * density.y * mie_phase(cos_theta);
+}
+
+// Distance from `origin`, relative to the earth's center, along `dir` until leaving the atmosphere
+// or hitting the ground, whichever comes first
+fn atmosphere_ray_length(origin: vec3<f32>, dir: vec3<f32>) -> f32 {
+    let b = dot(origin, dir);
+    let c = dot(origin, origin);
+    let ground = b * b - c + earth_radius * earth_radius;
+    if b < 0.0 && ground >= 0.0 {
+        return -b - sqrt(ground);
+    }
+    return -b + sqrt(max(b * b - c + atmosphere_radius * atmosphere_radius, 0.0));
+}
+
+// Fraction of the light coming from `light_dir` that reaches `height`.
+// Keep in sync with `light_transmittance` in Rust code.
+fn light_transmittance(height: f32, light_dir: vec3<f32>) -> vec3<f32> {
+    let origin = vec3(0.0, earth_radius + max(height, 0.0), 0.0);
+    let b = dot(origin, light_dir);
+    if b < 0.0 && b * b - dot(origin, origin) + earth_radius * earth_radius >= 0.0 {
+        // Below the horizon
+        return vec3(0.0);
+    }
+    const samples = 8;
+    let step = atmosphere_ray_length(origin, light_dir) / f32(samples);
+    var optical_depth = vec2(0.0);
+    for (var i = 0; i < samples; i++) {
+        let pos = origin + light_dir * (f32(i) + 0.5) * step;
+        optical_depth += atmosphere_density(length(pos) - earth_radius) * step;
+    }
+    return exp(-atmosphere_extinction(optical_depth));
+}
+
+// Sun- and moonlight scattered towards the viewer at `height`, looking along `dir` into the sky
+fn sky_light(height: f32, dir: vec3<f32>, env: Environment) -> vec3<f32> {
+    let origin = vec3(0.0, earth_radius + max(height, 0.0), 0.0);
+    const samples = 16;
+    let step = atmosphere_ray_length(origin, dir) / f32(samples);
+    let cos_sun = dot(dir, env.sun_dir);
+    let cos_moon = dot(dir, env.moon_dir);
+    let moon_intensity = moon_intensity(env.moon_illumination);
+
+    var out = airglow;
+    var optical_depth = vec2(0.0);
+    for (var i = 0; i < samples; i++) {
+        let pos = origin + dir * (f32(i) + 0.5) * step;
+        let sample_height = length(pos) - earth_radius;
+        let density = atmosphere_density(sample_height) * step;
+        optical_depth += density;
+        let transmittance = exp(-atmosphere_extinction(optical_depth - density * 0.5));
+        let sun = light_transmittance(sample_height, env.sun_dir) * sun_intensity;
+        let moon = light_transmittance(sample_height, env.moon_dir) * moon_intensity;
+        out += transmittance * (
+            sun * atmosphere_scattering(density, cos_sun)
+            + moon * atmosphere_scattering(density, cos_moon)
+        );
+    }
+    return out;
+}
+
+// Haze between the viewer at `height` and something `dist` away along `dir`, for distances short
+// enough to treat the density and lighting as constant. `height` is the camera's, whose
+// lighting `env` carries.
+// `color` is already exposed, the haze gets exposed here.
+fn aerial_perspective(color: vec3<f32>, height: f32, dir: vec3<f32>, dist: f32, env: Environment) -> vec3<f32> {
+    let density = atmosphere_density(height);
+    let extinction = atmosphere_extinction(density);
+    let transmittance = exp(-extinction * dist);
+    let sun = env.sun_transmittance * sun_intensity;
+    let moon = env.moon_transmittance * moon_intensity(env.moon_illumination);
+    // Integral of the in-scattering over the path
+    let in_scattering = (
+        sun * atmosphere_scattering(density, dot(dir, env.sun_dir))
+        + moon * atmosphere_scattering(density, dot(dir, env.moon_dir))
+    ) / extinction + airglow;
+    return color * transmittance + expose(in_scattering * (1.0 - transmittance));
+}
+
+// Fog of the weather between the viewer and something `dist` away, lit evenly from all sides.
+// `color` is already exposed.
+fn fog(color: vec3<f32>, dist: f32, env: Environment) -> vec3<f32> {
+    let transmittance = exp(-env.fog_density * dist);
+    if transmittance >= 1.0 {
+        return color;
+    }
+    let sun = env.ground_sun_transmittance * sun_intensity * max(env.sun_dir.y, 0.0);
+    let moon = env.ground_moon_transmittance * moon_intensity(env.moon_illumination) * max(env.moon_dir.y, 0.0);
+    let in_scattering = (sun + moon) * fog_albedo / (4.0 * PI) + airglow;
+    return mix(expose(in_scattering), color, transmittance);
+}
+
+// Of the fog droplets, with the light already dimmed by the clouds above
+const fog_albedo = 0.8;
+// The fog hugs the ground, so looking up leaves it sooner
+const fog_height = 500.0;
+
+// Distance through the fog along `dir` to the sky, from near the ground
+fn fog_dist_to_sky(dir: vec3<f32>) -> f32 {
+    return fog_height / max(dir.y, 0.01);
+}
+
+// Maps light to displayable colors
+fn expose(light: vec3<f32>) -> vec3<f32> {
+    return 1.0 - exp(-light);
+}
+
+// Layer of clouds between `cloud_bottom` and `cloud_top`, drifting with the wind
+
+// Distinct from the terrain octaves
+const cloud_salt = 0xc10du;
+// How fast the clouds change shape, relative to how fast the wind carries them
+const cloud_morph_speed = 0.2;
+
+// Extinction per unit of distance at `pos`, `time` seconds in.
+// Fewer `octaves` are cheaper but blurrier.
+fn cloud_density(pos: vec3<f32>, time: f32, octaves: i32, env: Environment) -> f32 {
+    let height = (pos.y - env.cloud_bottom) / (env.cloud_top - env.cloud_bottom);
+    if height <= 0.0 || height >= 1.0 {
+        return 0.0;
+    }
+    // Flat bottoms, rounded tops
+    let profile = smoothstep(0.0, 0.1, height) * (1.0 - smoothstep(0.4, 1.0, height));
+    let drifted = pos.xz - env.wind * time;
+    let morph = length(env.wind) * time * cloud_morph_speed;
+    let offset = seed_offset(env.seed, cloud_salt);
+    let p = vec3(drifted.x, pos.y + morph, drifted.y) * env.cloud_scale + vec3(offset.x, 0.0, offset.y);
+    let noise = fbm_simplex_3d(p, octaves, 2.0, 0.5) * 0.5 + 0.5;
+    return saturate((noise * profile - 1.0 + env.cloud_coverage) * 4.0) * env.cloud_density;
+}
+
+// Fraction of the light coming from `light_dir` that makes it through the clouds to `pos`
+fn cloud_transmittance(pos: vec3<f32>, light_dir: vec3<f32>, time: f32, samples: i32, env: Environment) -> f32 {
+    if light_dir.y <= 0.0 || env.cloud_coverage <= 0.0 {
+        return 1.0;
+    }
+    let start = max(env.cloud_bottom - pos.y, 0.0) / light_dir.y;
+    let end = max(env.cloud_top - pos.y, 0.0) / light_dir.y;
+    let step = (end - start) / f32(samples);
+    var optical_depth = 0.0;
+    for (var i = 0; i < samples; i++) {
+        let sample_pos = pos + light_dir * (start + (f32(i) + 0.5) * step);
+        optical_depth += cloud_density(sample_pos, time, 2, env) * step;
+    }
+    return exp(-optical_depth);
+}
@@ -1,75 +1,158 @@
 #import bevy_core_pipeline::fullscreen_vertex_shader::FullscreenVertexOutput
 #import bevy_pbr::{
-    atmosphere::functions::uv_to_ray_direction,
+    atmosphere::{
+        bindings::view,
+        functions::uv_to_ray_direction,
+    },
     mesh_view_bindings::globals,
 }
-#import noisy_bevy::fbm_simplex_3d
+#import noisy_bevy::{fbm_simplex_2d, fbm_simplex_3d}
 
-const low_sky_color = vec3(1.0, 0.7, 0.5);
-const high_sky_color = vec3(0.2, 0.4, 0.7);
+@group(0) @binding(32) var<uniform> environment: common::Environment;
 
 const sun_moon_size = 0.04;
-const sun_bloom_intensity = 0.00005;
 const moon_bloom_intensity = 0.00001;
+const moon_terminator_sharpness = 4.0;
+// Relative to the highlands
+const maria_albedo = 0.6;
 
-const cloud_vel = vec2(0.02, 0.05);
-const morph_factor = 0.05;
-const cloud_height = 0.5;
-const bright_cloud_brightness = 0.8;
-const dark_cloud_brightness = 0.4;
+// Grid cells per unit of the celestial sphere's radius, each holding at most one star
+const star_density = 150.0;
+const star_probability = 0.4;
+// Angular radius in radians, below half a cell so that a star only reaches the neighboring cells
+// on the nearer side
+const star_size = 0.0015;
+const star_brightness = 1.5;
+const twinkle_speed = 4.0;
+// Galactic north pole in equatorial coordinates (RA 192.86°, Dec 27.13°)
+const galactic_pole = vec3(-0.8677, -0.1981, 0.4560);
+const milky_way_width = 0.25;
+const milky_way_color = vec3(0.35, 0.35, 0.45);
 
 @fragment
 fn main(in: FullscreenVertexOutput) -> @location(0) vec4<f32> {
     let ray_dir = uv_to_ray_direction(in.uv);
-    let sun_dir = common::sun_dir(globals.time);
-    let moon_dir = common::moon_dir(sun_dir);
+    let sun_dir = environment.sun_dir;
+    let moon_dir = environment.moon_dir;
 
     let mapped_sun_height = common::map_sky_height(sun_dir.y);
     let mapped_moon_height = common::map_sky_height(moon_dir.y);
-    let brightness = common::sky_brightness(mapped_sun_height, mapped_moon_height);
 
-    var out = mix(
-        low_sky_color,
-        high_sky_color,
-        common::map_sky_height(ray_dir.y),
-    ) * brightness;
+    let height = view.world_position.y;
+    var out = common::expose(common::sky_light(height, ray_dir.xyz, environment));
 
+    let moon_dist = distance(ray_dir.xyz, moon_dir);
+    // Stars fade in as the sky darkens, and out towards the hazy horizon and under a bright moon
+    let night = (1.0 - mapped_sun_height)
+        * smoothstep(0.0, 0.2, ray_dir.y)
+        * (1.0 - 0.5 * mapped_moon_height * environment.moon_illumination);
+    // The moon hides the stars behind it, even its night side
+    if night > 0.0 && moon_dist >= sun_moon_size {
+        out += night_sky(ray_dir.xyz) * night;
+    }
+
+    // The glow around the sun comes from the scattering
     let sun_dist = distance(ray_dir.xyz, sun_dir);
-    var sun_intensity: f32;
     if sun_dist < sun_moon_size {
-        sun_intensity = mix(1.0, 0.9, sun_dist / sun_moon_size);
-    } else {
-        sun_intensity = pow(sun_bloom_intensity, sun_dist);
+        let sun_intensity = mix(1.0, 0.9, sun_dist / sun_moon_size);
+        out = mix(out, sun_color(), sun_intensity);
     }
-    out = mix(out, sun_color(mapped_sun_height), sun_intensity);
 
-    // TODO: add lunar phases (affects appearance and position of the moon)
-    let moon_dist = distance(ray_dir.xyz, moon_dir);
-    var moon_intensity: f32;
     if moon_dist < sun_moon_size {
-        moon_intensity = mix(1.0, 0.9, moon_dist / sun_moon_size);
+        let moon = moon_surface(ray_dir.xyz, moon_dir, sun_dir);
+        // The night side lets the sky shine through, like the real moon during the day
+        out = mix(out, moon_color(mapped_moon_height) * moon.x, moon.y);
     } else {
-        moon_intensity = pow(moon_bloom_intensity, moon_dist);
+        let moon_intensity = pow(moon_bloom_intensity, moon_dist) * environment.moon_illumination;
+        out = mix(out, moon_color(mapped_moon_height), moon_intensity);
     }
-    out = mix(out, moon_color(mapped_moon_height), moon_intensity);
-
-    let cloud_pos = vec2(
-        ray_dir.x * cloud_height / ray_dir.y + cloud_vel.x * globals.time,
-        ray_dir.z * cloud_height / ray_dir.y + cloud_vel.y * globals.time,
-    );
-    let noise = fbm_simplex_3d(vec3(cloud_pos, globals.time * morph_factor), 4, 2.0, 0.5) / 2.0 + 0.5;
-    let cloud_color = vec3(mix(bright_cloud_brightness, dark_cloud_brightness, noise) * brightness);
-    let dist_scale = pow(max(ray_dir.y, 0.0), 0.2);
-    out = mix(out, cloud_color, noise * dist_scale);
 
+    out = common::fog(out, common::fog_dist_to_sky(ray_dir.xyz), environment);
     return vec4(out, 1.0);
 }
 
-fn sun_color(mapped_sun_height: f32) -> vec3<f32> {
-    return mix(vec3(1.0, 0.2, 0.0), vec3(1.0, 0.9, 0.8), mapped_sun_height);
+// Reddened by the atmosphere near the horizon
+fn sun_color() -> vec3<f32> {
+    let transmittance = environment.sun_transmittance;
+    return transmittance / max(max(transmittance.r, max(transmittance.g, transmittance.b)), 1e-4);
+}
+
+// Stars and the Milky Way, fixed to the celestial sphere
+fn night_sky(ray_dir: vec3<f32>) -> vec3<f32> {
+    let dir = environment.equatorial_from_world * ray_dir;
+    var out = vec3(0.0);
+    var probability = star_probability;
+
+#ifdef MILKY_WAY
+    let galactic_latitude = dot(dir, galactic_pole);
+    let band = exp(-galactic_latitude * galactic_latitude / (milky_way_width * milky_way_width));
+    // Patchy glow with darker dust lanes
+    let glow = fbm_simplex_3d(dir * 6.0, 4, 2.0, 0.5) * 0.5 + 0.5;
+    let dust = smoothstep(0.1, 0.5, fbm_simplex_3d(dir * 12.0 + 5.0, 3, 2.0, 0.5));
+    out += milky_way_color * band * glow * (1.0 - 0.7 * dust);
+    probability = mix(probability, 1.0, band);
+#endif
+
+    // Stars near a cell's border spill into the neighbors, so check the 2x2x2 cells around the
+    // nearest corner
+    let first_cell = vec3<i32>(floor(dir * star_density - 0.5));
+    // Twinkling is stronger near the horizon, where the light passes through more air
+    let twinkle_amount = mix(0.6, 0.2, ray_dir.y);
+    for (var i = 0; i < 8; i++) {
+        let cell = first_cell + vec3(i & 1, (i >> 1u) & 1, (i >> 2u) & 1);
+        out += star(cell, dir, probability, twinkle_amount);
+    }
+    return out;
+}
+
+// Light of the star in `cell` reaching `dir`, if the cell holds one
+fn star(cell: vec3<i32>, dir: vec3<f32>, probability: f32, twinkle_amount: f32) -> vec3<f32> {
+    let h = common::hash(
+        bitcast<u32>(cell.x) ^ common::hash(bitcast<u32>(cell.y) ^ common::hash(bitcast<u32>(cell.z) ^ environment.seed))
+    );
+    let jitter = vec3(f32(h & 0x3ffu), f32((h >> 10u) & 0x3ffu), f32((h >> 20u) & 0x3ffu)) / 1024.0;
+    let star_dir = normalize(vec3<f32>(cell) + jitter);
+    let h2 = common::hash(h);
+    let rank = f32(h2 & 0xffffu) / 65536.0 / probability;
+    // A star projected into another cell belongs to that one, which would draw it again
+    if rank >= 1.0 || any(vec3<i32>(floor(star_dir * star_density)) != cell) {
+        return vec3(0.0);
+    }
+
+    // Many faint stars, few bright ones
+    let magnitude = pow(1.0 - rank, 4.0);
+    let twinkle_phase = f32((h2 >> 16u) & 0xffu);
+    let twinkle_rate = 1.0 + f32(h2 >> 24u) / 255.0;
+    let twinkle = 1.0 + twinkle_amount * sin(globals.time * twinkle_speed * twinkle_rate + twinkle_phase);
+    let color = mix(vec3(1.0, 0.8, 0.6), vec3(0.7, 0.8, 1.0), f32((h2 >> 8u) & 0xffu) / 255.0);
+    let intensity = smoothstep(star_size, 0.0, distance(dir, star_dir));
+    return color * intensity * magnitude * twinkle * star_brightness;
 }
 
-// TODO: add texture
 fn moon_color(mapped_moon_height: f32) -> vec3<f32> {
     return mix(vec3(0.5, 0.1, 0.0), vec3(0.5), mapped_moon_height);
 }
+
+// Appearance of the moon where `ray_dir` hits its disc.
+// Returns:
+// - x: albedo, darker in the maria
+// - y: how much sunlight falls onto it, 0 on the night side
+fn moon_surface(ray_dir: vec3<f32>, moon_dir: vec3<f32>, sun_dir: vec3<f32>) -> vec2<f32> {
+    // Position on the disc in [-1, 1]², y pointing away from the horizon
+    let reference = select(vec3(0.0, 1.0, 0.0), vec3(1.0, 0.0, 0.0), abs(moon_dir.y) > 0.999);
+    let right = normalize(cross(moon_dir, reference));
+    let up = cross(right, moon_dir);
+    let disc = vec2(dot(ray_dir - moon_dir, right), dot(ray_dir - moon_dir, up)) / sun_moon_size;
+
+    // Treat the disc as the half of a sphere facing us
+    let normal = disc.x * right + disc.y * up - sqrt(max(1.0 - dot(disc, disc), 0.0)) * moon_dir;
+    // The sun is far enough away to light the moon from the direction we see it in,
+    // which puts the terminator where the phase says.
+    // The moon's dusty surface looks evenly lit up to a soft terminator.
+    let light = clamp(dot(normal, sun_dir) * moon_terminator_sharpness, 0.0, 1.0);
+
+    // The moon always shows us the same side, so the maria stay in place
+    let maria = smoothstep(0.0, 0.4, fbm_simplex_2d(disc * 1.3 + vec2(2.7, 1.1), 3, 2.0, 0.5));
+    let albedo = mix(1.0, maria_albedo, maria);
+    return vec2(albedo, light);
+}
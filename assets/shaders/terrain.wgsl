@@ -1,91 +1,99 @@
 #import bevy_pbr::{
     forward_io::Vertex,
-    mesh_functions,
     mesh_view_bindings::{globals, view},
     view_transformations::position_world_to_clip
 }
-#import noisy_bevy::simplex_noise_2d
 
-const fog_density = 0.0002;
-const fog_color = vec3(0.6, 0.6, 0.8);
+// Clear of `StandardMaterial`'s bindings, see `TerrainMaterial` in Rust code.
+// The noise settings are bound in `terrain_noise.wgsl`, the LOD and environment in
+// `terrain_vertex.wgsl`.
+@group(2) @binding(101) var<uniform> shading: terrain_biomes::TerrainShading;
 
-struct VertexOutput {
-    @builtin(position) clip_pos: vec4<f32>,
-    @location(0) world_pos: vec3<f32>,
-    @location(1) slope: vec2<f32>,
-}
+// Per light, for the shadows of the clouds
+const cloud_shadow_samples = 3;
 
-@vertex
-fn vertex(in: Vertex) -> VertexOutput {
-    var out: VertexOutput;
+// Marching towards the sun or moon over the terrain, with steps growing with the distance
+const shadow_steps = 24;
+const shadow_start = 2.0;
+const shadow_max_dist = 2000.0;
+// Fewer than the terrain's, the finest octaves cast hardly any shadow
+const shadow_octaves = 5.0;
+// Higher values give harder shadow edges
+const shadow_sharpness = 16.0;
 
-    let world_from_local = mesh_functions::get_world_from_local(in.instance_index);
-    out.world_pos = mesh_functions::mesh_position_local_to_world(world_from_local, vec4(in.position, 1.0)).xyz;
-    let noise = noise(out.world_pos.xz);
-    out.world_pos.y = noise.x;
-    out.slope = noise.yz;
-    out.clip_pos = position_world_to_clip(out.world_pos.xyz);
+@vertex
+fn vertex(in: Vertex) -> terrain_vertex::VertexOutput {
+    var out: terrain_vertex::VertexOutput;
+    out.world_pos = terrain_vertex::displace(in.instance_index, in.position).world_pos;
+    out.clip_pos = position_world_to_clip(out.world_pos);
     return out;
 }
 
 @fragment
-fn fragment(in: VertexOutput) -> @location(0) vec4<f32> {
-    let normal = normalize(vec3(-in.slope.x, 1.0, -in.slope.y));
-    let sun_dir = common::sun_dir(globals.time);
-    let moon_dir = common::moon_dir(sun_dir);
+fn fragment(in: terrain_vertex::VertexOutput) -> @location(0) vec4<f32> {
+    let environment = terrain_vertex::environment;
+    // Shared by the shape and the colors of the terrain
+    let climate = terrain_noise::climate(in.world_pos.xz);
+    // Per pixel, for the lighting not to change with the LOD
+    let slope = terrain_noise::pixel_slope(in.world_pos, climate);
+    let normal = normalize(vec3(-slope.x, 1.0, -slope.y));
+    let to_fragment = in.world_pos - view.world_position;
+    let surface = terrain_layers::terrain_surface(in.world_pos, slope, climate.value, length(to_fragment), environment, shading);
+    let sun_dir = environment.sun_dir;
+    let moon_dir = environment.moon_dir;
     let sun_height = common::map_sky_height(sun_dir.y);
     let moon_height = common::map_sky_height(moon_dir.y);
-    let sky_brightness = common::sky_brightness(sun_height, moon_height);
+    let sun_shadow = common::cloud_transmittance(in.world_pos, sun_dir, globals.time, cloud_shadow_samples, environment);
+    let moon_shadow = common::cloud_transmittance(in.world_pos, moon_dir, globals.time, cloud_shadow_samples, environment);
+    let sun_terrain_shadow = terrain_shadow(in.world_pos, normal, sun_dir);
+    // Faint next to the sun, not worth the cost then
+    var moon_terrain_shadow = 1.0;
+    if sun_dir.y <= 0.0 {
+        moon_terrain_shadow = terrain_shadow(in.world_pos, normal, moon_dir);
+    }
 
     let brightness = clamp(
-        max(dot(normal, sun_dir) * sun_height, 0.0) +
-        max(dot(normal, moon_dir) * moon_height * common::moon_brightness, 0.0),
+        max(dot(surface.normal, sun_dir) * sun_height * sun_shadow * sun_terrain_shadow, 0.0) +
+        max(dot(surface.normal, moon_dir) * moon_height * moon_shadow * moon_terrain_shadow * common::moon_brightness * environment.moon_illumination, 0.0),
         0.1,
         1.0,
     );
-    let slope = clamp(length(in.slope * 0.5), 0.0, 1.0);
-    let albedo = mix(common::grass_color, vec3(0.2, 0.2, 0.1), slope);
-    var out = albedo * brightness;
-    let fog = exp(-fog_density * distance(in.world_pos, view.world_position));
-    out = mix(fog_color * sky_brightness, out, fog);
+    var out = surface.albedo * brightness;
+    out = common::aerial_perspective(
+        out,
+        view.world_position.y,
+        normalize(to_fragment),
+        length(to_fragment) * shading.aerial_perspective_scale,
+        environment,
+    );
+    out = common::fog(out, length(to_fragment), environment);
     return vec4(out, 1.0);
 }
 
-// Returns:
-// - x: height
-// - yz: slope
-fn noise(pos: vec2<f32>) -> vec3<f32> {
-    // Update `TERRAIN_MIN_HEIGHT` and `TERRAIN_MAX_HEIGHT` in Rust code
-    // when changing these values
-
-    // https://youtu.be/gsJHzBTPG0Y
-    const slope_amp_falloff = 10.0;
-
-    var freq = 0.005;
-    var amp = 1.0;
-
-    var height = 0.5;
-    var slope = vec2(0.0);
-
-    for (var octave = 0; octave < 9; octave++) {
-        let y = simplex_noise_2d(pos * freq);
-        // TODO: calculate using the derivative
-        slope += vec2(
-            simplex_noise_2d(vec2(pos.x + 0.01, pos.y) * freq) - y,
-            simplex_noise_2d(vec2(pos.x, pos.y + 0.01) * freq) - y,
-        ) / 0.01 * amp;
-        height += y * amp / (1.0 + slope_amp_falloff * length(slope));
-        freq *= 2.0;
-        amp *= 0.5;
+// Fraction of the light coming from `light_dir` that the terrain lets through to `pos`,
+// soft at the edges after https://iquilezles.org/articles/rmshadows/
+fn terrain_shadow(pos: vec3<f32>, normal: vec3<f32>, light_dir: vec3<f32>) -> f32 {
+    if light_dir.y <= 0.0 || dot(normal, light_dir) <= 0.0 {
+        return 0.0;
     }
-    return vec3(transform_height(height), slope * transform_height_derivative(height));
-}
-
-fn transform_height(height: f32) -> f32 {
-    return mix(height, height * height, sign(height) * 0.5 + 0.5) * 15.0;
-}
-
-fn transform_height_derivative(height: f32) -> f32 {
-    // Power rule
-    return mix(1.0, 2.0 * height, sign(height) * 0.5 + 0.5) * 15.0;
+    // Clear of the coarser surface the shadows are marched over
+    let origin = pos + normal * shadow_start;
+    let growth = pow(shadow_max_dist / shadow_start, 1.0 / f32(shadow_steps));
+    var shadow = 1.0;
+    var t = shadow_start;
+    for (var i = 0; i < shadow_steps; i++) {
+        let sample_pos = origin + light_dir * t;
+        if sample_pos.y > terrain_vertex::lod.max_height {
+            break;
+        }
+        let octaves = min(shadow_octaves, f32(terrain_noise::noise_settings.octaves));
+        let climate = terrain_noise::climate(sample_pos.xz);
+        let height = terrain_noise::noise_octaves(sample_pos.xz, octaves, climate).x;
+        shadow = min(shadow, shadow_sharpness * (sample_pos.y - height) / t);
+        if shadow <= 0.0 {
+            return 0.0;
+        }
+        t *= growth;
+    }
+    return shadow;
 }
@@ -0,0 +1,171 @@
+#define_import_path terrain_biomes
+
+// Keep in sync with `biome.rs`, which also documents what these are
+
+// Indices into the weights of all biomes, in the order of `Biome`
+const grassland = 0;
+const desert = 1;
+const tundra = 2;
+const forest = 3;
+const swamp = 4;
+const alpine = 5;
+const beach = 6;
+const biome_count = 7;
+// The first biomes, which shape the terrain
+const climate_biome_count = 5;
+
+const climate_blend = 0.12;
+const alpine_height = 50.0;
+const alpine_blend = 10.0;
+const beach_height = 1.0;
+const beach_depth = 1.0;
+const beach_blend = 1.0;
+
+const temperature_salt = 0x7e4a0u;
+const moisture_salt = 0x3015eu;
+const climate_offset_scale = 1.0 / 64.0;
+
+// `TerrainShading` in Rust code
+struct TerrainShading {
+    aerial_perspective_scale: f32,
+    grass_color: vec4<f32>,
+    desert_color: vec4<f32>,
+    tundra_color: vec4<f32>,
+    forest_color: vec4<f32>,
+    swamp_color: vec4<f32>,
+    alpine_color: vec4<f32>,
+    beach_color: vec4<f32>,
+    rock_color: vec4<f32>,
+    dirt_color: vec4<f32>,
+    snow_color: vec4<f32>,
+    slope_threshold: f32,
+    snow_line: f32,
+    snow_blend: f32,
+    snow_max_slope: f32,
+    snow_sun_melt: f32,
+    texture_size: f32,
+    far_texture_scale: f32,
+    detail_distance: f32,
+}
+
+// Temperature and moisture at a point, each in [0, 1], with their gradients
+struct Climate {
+    value: vec2<f32>,
+    temperature_gradient: vec2<f32>,
+    moisture_gradient: vec2<f32>,
+}
+
+fn climate(pos: vec2<f32>, frequency: f32, seed: u32) -> Climate {
+    let temperature = climate_field(pos, frequency, seed, temperature_salt);
+    let moisture = climate_field(pos, frequency, seed, moisture_salt);
+    return Climate(vec2(temperature.x, moisture.x), temperature.yz, moisture.yz);
+}
+
+// Returns:
+// - x: value
+// - yz: gradient
+fn climate_field(pos: vec2<f32>, frequency: f32, seed: u32, salt: u32) -> vec3<f32> {
+    let p = pos * frequency + common::seed_offset(seed, salt) * climate_offset_scale;
+    let a = common::simplex_noise_2d_derivative(p);
+    let b = common::simplex_noise_2d_derivative(p * 2.0);
+    let n = (a.x + 0.5 * b.x) / 1.5;
+    // Stretched, as the extremes of the noise are rare
+    let value = 0.5 + 0.7 * n;
+    let gradient = 0.7 * (a.yz + b.yz) / 1.5 * frequency;
+    if value < 0.0 || value > 1.0 {
+        return vec3(saturate(value), 0.0, 0.0);
+    }
+    return vec3(value, gradient);
+}
+
+// Gradient of a function of the climate, given its derivatives by temperature and moisture
+fn climate_chain(climate: Climate, derivative: vec2<f32>) -> vec2<f32> {
+    return derivative.x * climate.temperature_gradient + derivative.y * climate.moisture_gradient;
+}
+
+// Weights of the climate biomes, summing to 1, with their derivatives by temperature and moisture
+struct ClimateWeights {
+    weights: array<f32, climate_biome_count>,
+    derivatives: array<vec2<f32>, climate_biome_count>,
+}
+
+fn climate_weights(climate: vec2<f32>) -> ClimateWeights {
+    var centers = array(
+        vec2(0.55, 0.35),
+        vec2(0.85, 0.15),
+        vec2(0.15, 0.45),
+        vec2(0.45, 0.7),
+        vec2(0.8, 0.85),
+    );
+    var out: ClimateWeights;
+    var sum = 0.0;
+    var sum_derivative = vec2(0.0);
+    for (var i = 0; i < climate_biome_count; i++) {
+        let d = climate - centers[i];
+        out.weights[i] = exp(-dot(d, d) / (climate_blend * climate_blend));
+        out.derivatives[i] = out.weights[i] * -2.0 * d / (climate_blend * climate_blend);
+        sum += out.weights[i];
+        sum_derivative += out.derivatives[i];
+    }
+    sum = max(sum, 1e-30);
+    for (var i = 0; i < climate_biome_count; i++) {
+        out.weights[i] /= sum;
+        // Quotient rule
+        out.derivatives[i] = (out.derivatives[i] - out.weights[i] * sum_derivative) / sum;
+    }
+    return out;
+}
+
+// `BiomeShape` in Rust code
+struct BiomeShape {
+    amplitude: f32,
+    roughness: f32,
+    amplitude_gradient: vec2<f32>,
+    roughness_gradient: vec2<f32>,
+}
+
+fn biome_shape(climate: Climate) -> BiomeShape {
+    // Amplitude (x) and roughness (y) of each climate biome
+    var shapes = array(
+        vec2(1.0, 1.0),
+        vec2(0.5, 0.85),
+        vec2(1.4, 1.05),
+        vec2(1.2, 1.0),
+        vec2(0.3, 0.7),
+    );
+    let weights = climate_weights(climate.value);
+    var shape = BiomeShape(0.0, 0.0, vec2(0.0), vec2(0.0));
+    for (var i = 0; i < climate_biome_count; i++) {
+        let gradient = climate_chain(climate, weights.derivatives[i]);
+        shape.amplitude += shapes[i].x * weights.weights[i];
+        shape.roughness += shapes[i].y * weights.weights[i];
+        shape.amplitude_gradient += shapes[i].x * gradient;
+        shape.roughness_gradient += shapes[i].y * gradient;
+    }
+    return shape;
+}
+
+fn biome_weights(climate: vec2<f32>, altitude: f32) -> array<f32, biome_count> {
+    let alpine_weight = smoothstep(alpine_height - alpine_blend, alpine_height + alpine_blend, altitude);
+    let beach_weight = (1.0 - smoothstep(beach_height, beach_height + beach_blend, altitude))
+        * smoothstep(-beach_depth - beach_blend, -beach_depth, altitude)
+        * (1.0 - alpine_weight);
+    let climate_biome_weights = climate_weights(climate).weights;
+    var weights: array<f32, biome_count>;
+    for (var i = 0; i < climate_biome_count; i++) {
+        weights[i] = climate_biome_weights[i] * (1.0 - alpine_weight - beach_weight);
+    }
+    weights[alpine] = alpine_weight;
+    weights[beach] = beach_weight;
+    return weights;
+}
+
+fn biome_albedo(weights: array<f32, biome_count>, shading: TerrainShading) -> vec3<f32> {
+    return shading.grass_color.rgb * weights[grassland]
+        + shading.desert_color.rgb * weights[desert]
+        + shading.tundra_color.rgb * weights[tundra]
+        + shading.forest_color.rgb * weights[forest]
+        + shading.swamp_color.rgb * weights[swamp]
+        + shading.alpine_color.rgb * weights[alpine]
+        + shading.beach_color.rgb * weights[beach];
+}
@@ -0,0 +1,227 @@
+#define_import_path terrain_layers
+
+#import noisy_bevy::simplex_noise_2d
+
+// Materials layered over the terrain, see `TerrainMaterial` in Rust code for their textures
+
+// Indices into the texture arrays
+const grass = 0;
+const rock = 1;
+const dirt = 2;
+const sand = 3;
+const snow = 4;
+const layer_count = 5;
+
+// Clear of `terrain.wgsl`'s and `StandardMaterial`'s bindings
+@group(2) @binding(104) var albedo_textures: texture_2d_array<f32>;
+@group(2) @binding(105) var layer_sampler: sampler;
+@group(2) @binding(106) var normal_textures: texture_2d_array<f32>;
+@group(2) @binding(107) var roughness_textures: texture_2d_array<f32>;
+
+// Fractions of `TerrainShading::slope_threshold` over which dirt, then rock, show through
+const dirt_slope = vec2(0.3, 0.7);
+const rock_slope = vec2(0.6, 1.0);
+// Breaks up the snow line, in world units
+const snow_line_noise_scale = 0.02;
+const snow_line_salt = 0x5e0u;
+
+// Layers and projections contributing less are skipped
+const min_weight = 0.01;
+// Higher values narrow the blend between the projections
+const triplanar_sharpness = 4.0;
+// The albedo textures are normalized to average `ALBEDO_MEAN` in `tools/terrain_textures.py`,
+// which this scales back to the layer's color
+const albedo_scale = 1.0 / 0.5;
+
+// Anti-tiling: each patch of terrain offsets the textures by one of a few variants,
+// blending into the next variant at the patch edges
+const variant_count = 8.0;
+// In world units
+const variant_frequency = 0.01;
+const variant_salt = 0xa71u;
+
+// With the normal in world space
+struct TerrainSurface {
+    albedo: vec3<f32>,
+    normal: vec3<f32>,
+    roughness: f32,
+}
+
+// One layer sampled in one projection, with the normal in tangent space
+struct LayerSample {
+    albedo: vec3<f32>,
+    normal: vec3<f32>,
+    roughness: f32,
+}
+
+struct Variants {
+    offset_a: vec2<f32>,
+    offset_b: vec2<f32>,
+    // Of `offset_b`
+    blend: f32,
+}
+
+// Surface of the terrain at `pos` with the given slope and climate, layering dirt and rock
+// on steeper ground and snow above the snow line over the biomes.
+// `dist` is the distance to the camera.
+fn terrain_surface(
+    pos: vec3<f32>,
+    slope: vec2<f32>,
+    climate: vec2<f32>,
+    dist: f32,
+    environment: common::Environment,
+    shading: terrain_biomes::TerrainShading,
+) -> TerrainSurface {
+    // Outside of any branch, as the samples below are skipped depending on the weights
+    let pos_dx = dpdx(pos);
+    let pos_dy = dpdy(pos);
+    let normal = normalize(vec3(-slope.x, 1.0, -slope.y));
+
+    var weights: array<f32, layer_count>;
+    var colors: array<vec3<f32>, layer_count>;
+    let biomes = terrain_biomes::biome_weights(climate, pos.y);
+    let sandy = biomes[terrain_biomes::desert] + biomes[terrain_biomes::beach];
+    weights[sand] = sandy;
+    colors[sand] = (shading.desert_color.rgb * biomes[terrain_biomes::desert]
+        + shading.beach_color.rgb * biomes[terrain_biomes::beach]) / max(sandy, 1e-6);
+    weights[grass] = 1.0 - sandy;
+    colors[grass] = (terrain_biomes::biome_albedo(biomes, shading) - colors[sand] * sandy) / max(1.0 - sandy, 1e-6);
+    colors[dirt] = shading.dirt_color.rgb;
+    colors[rock] = shading.rock_color.rgb;
+    colors[snow] = shading.snow_color.rgb;
+
+    let steepness = length(slope) / shading.slope_threshold;
+    add_layer(&weights, dirt, smoothstep(dirt_slope.x, dirt_slope.y, steepness));
+    add_layer(&weights, rock, smoothstep(rock_slope.x, rock_slope.y, steepness));
+
+    // Snow melts sooner where the slope faces the sun's path, raising the snow line there
+    let sun_facing = dot(-slope, noon_sun_dir(environment).xz);
+    let snow_line_noise = simplex_noise_2d(
+        pos.xz * snow_line_noise_scale + common::seed_offset(environment.seed, snow_line_salt),
+    );
+    let snow_line = shading.snow_line + shading.snow_sun_melt * sun_facing + shading.snow_blend * snow_line_noise;
+    add_layer(
+        &weights,
+        snow,
+        smoothstep(snow_line - shading.snow_blend, snow_line + shading.snow_blend, pos.y)
+            // Only settles on flat enough ground
+            * (1.0 - smoothstep(0.5 * shading.snow_max_slope, shading.snow_max_slope, length(slope))),
+    );
+
+    var blend = pow(abs(normal), vec3(triplanar_sharpness));
+    blend /= blend.x + blend.y + blend.z;
+    let variants = texture_variants(pos, environment.seed);
+    // Finer textures up close, coarser ones in the distance to hide the tiling
+    let far_size = shading.texture_size * shading.far_texture_scale;
+    let far = smoothstep(0.5 * shading.detail_distance, shading.detail_distance, dist);
+
+    var surface = TerrainSurface(vec3(0.0), vec3(0.0), 0.0);
+    for (var layer = 0; layer < layer_count; layer++) {
+        if weights[layer] < min_weight {
+            continue;
+        }
+        var sample = LayerSample(vec3(0.0), vec3(0.0), 0.0);
+        if far < 1.0 {
+            let near = sample_triplanar(layer, pos, pos_dx, pos_dy, shading.texture_size, normal, blend, variants);
+            sample = scale_sample(near, 1.0 - far);
+        }
+        if far > 0.0 {
+            let far_sample = sample_triplanar(layer, pos, pos_dx, pos_dy, far_size, normal, blend, variants);
+            sample = add_sample(sample, scale_sample(far_sample, far));
+        }
+        surface.albedo += sample.albedo * colors[layer] * albedo_scale * weights[layer];
+        surface.normal += sample.normal * weights[layer];
+        surface.roughness += sample.roughness * weights[layer];
+    }
+    surface.normal = normalize(surface.normal);
+    return surface;
+}
+
+// Covers the layers by `weight` of `layer`
+fn add_layer(weights: ptr<function, array<f32, layer_count>>, layer: i32, weight: f32) {
+    for (var i = 0; i < layer_count; i++) {
+        (*weights)[i] *= 1.0 - weight;
+    }
+    (*weights)[layer] += weight;
+}
+
+// Direction of the sun at noon around the equinoxes, on the meridian towards the equator
+fn noon_sun_dir(environment: common::Environment) -> vec3<f32> {
+    // Rows of `equatorial_from_world` are the equatorial axes in world space
+    let pole = vec3(0.0, 0.0, 1.0) * environment.equatorial_from_world;
+    let toward_equator = vec3(0.0, 1.0, 0.0) - pole * pole.y;
+    // Straight up at the poles, where the sun circles the horizon
+    return select(vec3(0.0, 1.0, 0.0), normalize(toward_equator), length(toward_equator) > 1e-4);
+}
+
+fn texture_variants(pos: vec3<f32>, seed: u32) -> Variants {
+    let offset = common::seed_offset(seed, variant_salt);
+    let v = (simplex_noise_2d(pos.xz * variant_frequency + offset) * 0.5 + 0.5) * variant_count;
+    let i = u32(floor(v));
+    return Variants(
+        fract(common::seed_offset(seed ^ i, variant_salt)),
+        fract(common::seed_offset(seed ^ (i + 1u), variant_salt)),
+        smoothstep(0.3, 0.7, fract(v)),
+    );
+}
+
+// `layer` projected along each axis onto tiles `size` wide, with the normal in world space.
+// After https://bgolus.medium.com/normal-mapping-for-a-triplanar-shader-10bf39dca05a
+fn sample_triplanar(
+    layer: i32,
+    pos: vec3<f32>,
+    pos_dx: vec3<f32>,
+    pos_dy: vec3<f32>,
+    size: f32,
+    normal: vec3<f32>,
+    blend: vec3<f32>,
+    variants: Variants,
+) -> LayerSample {
+    let uv = pos / size;
+    let uv_dx = pos_dx / size;
+    let uv_dy = pos_dy / size;
+    var out = LayerSample(vec3(0.0), vec3(0.0), 0.0);
+    if blend.x > min_weight {
+        var x = sample_projection(layer, uv.zy, uv_dx.zy, uv_dy.zy, variants);
+        // Whiteout blend with the surface normal
+        x.normal = vec3(x.normal.xy + normal.zy, abs(x.normal.z) * normal.x).zyx;
+        out = add_sample(out, scale_sample(x, blend.x));
+    }
+    if blend.y > min_weight {
+        var y = sample_projection(layer, uv.xz, uv_dx.xz, uv_dy.xz, variants);
+        y.normal = vec3(y.normal.xy + normal.xz, abs(y.normal.z) * normal.y).xzy;
+        out = add_sample(out, scale_sample(y, blend.y));
+    }
+    if blend.z > min_weight {
+        var z = sample_projection(layer, uv.xy, uv_dx.xy, uv_dy.xy, variants);
+        z.normal = vec3(z.normal.xy + normal.xy, abs(z.normal.z) * normal.z);
+        out = add_sample(out, scale_sample(z, blend.z));
+    }
+    out.normal = normalize(out.normal);
+    return out;
+}
+
+// Explicit gradients, as the calling code skips samples depending on the weights
+fn sample_projection(layer: i32, uv: vec2<f32>, uv_dx: vec2<f32>, uv_dy: vec2<f32>, variants: Variants) -> LayerSample {
+    let a = sample_textures(layer, uv + variants.offset_a, uv_dx, uv_dy);
+    let b = sample_textures(layer, uv + variants.offset_b, uv_dx, uv_dy);
+    let sample = add_sample(scale_sample(a, 1.0 - variants.blend), scale_sample(b, variants.blend));
+    return LayerSample(sample.albedo, normalize(sample.normal), sample.roughness);
+}
+
+fn sample_textures(layer: i32, uv: vec2<f32>, uv_dx: vec2<f32>, uv_dy: vec2<f32>) -> LayerSample {
+    let albedo = textureSampleGrad(albedo_textures, layer_sampler, uv, layer, uv_dx, uv_dy).rgb;
+    // Only X and Y are stored
+    let normal_xy = textureSampleGrad(normal_textures, layer_sampler, uv, layer, uv_dx, uv_dy).xy * 2.0 - 1.0;
+    let normal = vec3(normal_xy, sqrt(saturate(1.0 - dot(normal_xy, normal_xy))));
+    let roughness = textureSampleGrad(roughness_textures, layer_sampler, uv, layer, uv_dx, uv_dy).x;
+    return LayerSample(albedo, normal, roughness);
+}
+
+fn scale_sample(sample: LayerSample, weight: f32) -> LayerSample {
+    return LayerSample(sample.albedo * weight, sample.normal * weight, sample.roughness * weight);
+}
+
+fn add_sample(a: LayerSample, b: LayerSample) -> LayerSample {
+    return LayerSample(a.albedo + b.albedo, a.normal + b.normal, a.roughness + b.roughness);
+}
@@ -0,0 +1,102 @@
+#define_import_path terrain_noise
+
+// Keep in sync with `TerrainNoiseSettings` in Rust code
+struct TerrainNoiseSettings {
+    frequency: f32,
+    octaves: u32,
+    gain: f32,
+    start_height: f32,
+    amplitude: f32,
+    slope_falloff: f32,
+    climate_frequency: f32,
+    seed: u32,
+}
+
+// See `TerrainMaterial` in Rust code
+@group(2) @binding(100) var<uniform> noise_settings: TerrainNoiseSettings;
+
+// Octaves with shorter wavelengths than this many pixels are left out of the per pixel slope,
+// where they would only alias
+const min_wavelength_pixels = 4.0;
+
+// Returns:
+// - x: height
+// - yz: slope
+fn noise(pos: vec2<f32>) -> vec3<f32> {
+    return noise_octaves(pos, f32(noise_settings.octaves), climate(pos));
+}
+
+fn climate(pos: vec2<f32>) -> terrain_biomes::Climate {
+    return terrain_biomes::climate(pos, noise_settings.climate_frequency, noise_settings.seed);
+}
+
+// `noise` with only the first `octaves`, the last one faded in by the fraction, given the
+// `climate` at `pos`. The CPU only samples all octaves, as in `noise`.
+fn noise_octaves(pos: vec2<f32>, octaves: f32, climate: terrain_biomes::Climate) -> vec3<f32> {
+    // Keep in sync with `TerrainHeightField` in Rust code
+
+    let shape = terrain_biomes::biome_shape(climate);
+    let gain = noise_settings.gain * shape.roughness;
+    var freq = noise_settings.frequency;
+    var amp = 1.0;
+    // Derivative of `amp` by `gain`
+    var amp_per_gain = 0.0;
+
+    var height = noise_settings.start_height;
+    // Of the octaves so far, undamped, to damp the later ones
+    var slope = vec2(0.0);
+    // Derivatives of `slope` by position and by gain, for those of the damping
+    var slope_jacobian = mat2x2(0.0, 0.0, 0.0, 0.0);
+    var slope_per_gain = vec2(0.0);
+    var gradient = vec2(0.0);
+    var height_per_gain = 0.0;
+
+    for (var octave = 0u; f32(octave) < octaves; octave++) {
+        let fade = saturate(octaves - f32(octave));
+        let offset = common::seed_offset(noise_settings.seed, octave);
+        let derivatives = common::simplex_noise_2d_derivatives(pos * freq + offset);
+        let n = derivatives.first;
+        // Chain rule
+        slope += n.yz * freq * amp * fade;
+        slope_jacobian += derivatives.hessian * (freq * freq * amp * fade);
+        slope_per_gain += n.yz * freq * amp_per_gain * fade;
+        let damping = 1.0 + noise_settings.slope_falloff * length(slope);
+        let direction = select(vec2(0.0), normalize(slope), length(slope) > 0.0);
+        let damping_gradient = noise_settings.slope_falloff * (slope_jacobian * direction);
+        let damping_per_gain = noise_settings.slope_falloff * dot(direction, slope_per_gain);
+        height += n.x * amp * fade / damping;
+        // Quotient rule
+        gradient += (n.yz * freq * amp * damping - n.x * amp * damping_gradient) * fade / (damping * damping);
+        height_per_gain += (n.x * amp_per_gain * damping - n.x * amp * damping_per_gain) * fade
+            / (damping * damping);
+        freq *= 2.0;
+        amp_per_gain = amp_per_gain * gain + amp;
+        amp *= gain;
+    }
+    // The roughness changes the gain, and with it the later octaves
+    gradient += height_per_gain * noise_settings.gain * shape.roughness_gradient;
+    let transformed = transform_height(height);
+    return vec3(
+        transformed * shape.amplitude,
+        gradient * transform_height_derivative(height) * shape.amplitude + transformed * shape.amplitude_gradient,
+    );
+}
+
+// Slope of the terrain at `pos` with as many octaves as the pixel resolves, independent of
+// the vertex spacing of the chunk. `climate` is the one at `pos`.
+// Call from uniform control flow, for the derivatives.
+fn pixel_slope(pos: vec3<f32>, climate: terrain_biomes::Climate) -> vec2<f32> {
+    let footprint = max(length(dpdx(pos)), length(dpdy(pos)));
+    // The wavelength of the first octave is 1 / frequency, halving with every octave
+    let octaves = log2(1.0 / (noise_settings.frequency * footprint * min_wavelength_pixels)) + 1.0;
+    return noise_octaves(pos.xz, clamp(octaves, 1.0, f32(noise_settings.octaves)), climate).yz;
+}
+
+fn transform_height(height: f32) -> f32 {
+    return mix(height, height * height, sign(height) * 0.5 + 0.5) * noise_settings.amplitude;
+}
+
+fn transform_height_derivative(height: f32) -> f32 {
+    // Power rule
+    return mix(1.0, 2.0 * height, sign(height) * 0.5 + 0.5) * noise_settings.amplitude;
+}
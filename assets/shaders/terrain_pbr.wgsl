@@ -0,0 +1,106 @@
+// Fragment stage of the terrain with `--pbr`, see `terrain.wgsl` for the rest
+
+#import bevy_pbr::{
+    lighting,
+    mesh_types,
+    mesh_view_bindings::{globals, lights, view},
+    mesh_view_types,
+    pbr_bindings,
+    pbr_functions,
+    pbr_types,
+    shadows,
+}
+
+// The noise settings are bound in `terrain_noise.wgsl`, the environment in `terrain_vertex.wgsl`
+@group(2) @binding(101) var<uniform> shading: terrain_biomes::TerrainShading;
+
+// Per light, for the shadows of the clouds
+const cloud_shadow_samples = 3;
+
+// The light of the sun and moon that the clouds hold back from `apply_pbr_lighting`'s result,
+// which only knows of the shadow maps. Redoes its directional lights with the inputs it derives.
+fn cloud_shadowed_light(in: pbr_types::PbrInput, environment: common::Environment) -> vec3<f32> {
+    let base_color = in.material.base_color.rgb;
+    let metallic = in.material.metallic;
+    let perceptual_roughness = in.material.perceptual_roughness;
+    let NdotV = max(dot(in.N, in.V), 0.0001);
+
+    var lighting_input: lighting::LightingInput;
+    lighting_input.layers[lighting::LAYER_BASE].NdotV = NdotV;
+    lighting_input.layers[lighting::LAYER_BASE].N = in.N;
+    lighting_input.layers[lighting::LAYER_BASE].R = reflect(-in.V, in.N);
+    lighting_input.layers[lighting::LAYER_BASE].perceptual_roughness = perceptual_roughness;
+    lighting_input.layers[lighting::LAYER_BASE].roughness = lighting::perceptualRoughnessToRoughness(perceptual_roughness);
+    lighting_input.P = in.world_position.xyz;
+    lighting_input.V = in.V;
+    lighting_input.diffuse_color = pbr_functions::calculate_diffuse_color(
+        base_color,
+        metallic,
+        in.material.specular_transmission,
+        in.material.diffuse_transmission,
+    );
+    lighting_input.F0_ = pbr_functions::calculate_F0(base_color, metallic, in.material.reflectance);
+    lighting_input.F_ab = lighting::F_AB(perceptual_roughness, NdotV);
+
+    let view_z = dot(vec4(
+        view.view_from_world[0].z,
+        view.view_from_world[1].z,
+        view.view_from_world[2].z,
+        view.view_from_world[3].z,
+    ), in.world_position);
+
+    var held_back = vec3(0.0);
+    for (var i = 0u; i < lights.n_directional_lights; i++) {
+        let light = &lights.directional_lights[i];
+        let cloud_shadow = common::cloud_transmittance(
+            in.world_position.xyz,
+            (*light).direction_to_light,
+            globals.time,
+            cloud_shadow_samples,
+            environment,
+        );
+        if cloud_shadow >= 1.0 {
+            continue;
+        }
+        var shadow = 1.0;
+        if ((*light).flags & mesh_view_types::DIRECTIONAL_LIGHT_FLAGS_SHADOWS_ENABLED_BIT) != 0u {
+            shadow = shadows::fetch_directional_shadow(i, in.world_position, in.world_normal, view_z);
+        }
+        held_back += lighting::directional_light(i, &lighting_input, true) * shadow * (1.0 - cloud_shadow);
+    }
+    return held_back * view.exposure;
+}
+
+@fragment
+fn fragment(in: terrain_vertex::VertexOutput) -> @location(0) vec4<f32> {
+    let environment = terrain_vertex::environment;
+    // Shared by the shape and the colors of the terrain
+    let climate = terrain_noise::climate(in.world_pos.xz);
+    // Per pixel, for the lighting not to change with the LOD
+    let slope = terrain_noise::pixel_slope(in.world_pos, climate);
+    let to_fragment = in.world_pos - view.world_position;
+    let surface = terrain_layers::terrain_surface(in.world_pos, slope, climate.value, length(to_fragment), environment, shading);
+
+    var pbr_input = pbr_types::pbr_input_new();
+    pbr_input.material = pbr_bindings::material;
+    pbr_input.material.base_color = vec4(surface.albedo, 1.0);
+    pbr_input.material.perceptual_roughness = surface.roughness;
+    pbr_input.frag_coord = in.clip_pos;
+    pbr_input.world_position = vec4(in.world_pos, 1.0);
+    pbr_input.world_normal = normalize(vec3(-slope.x, 1.0, -slope.y));
+    pbr_input.N = surface.normal;
+    pbr_input.V = pbr_functions::calculate_view(pbr_input.world_position, false);
+    pbr_input.flags = mesh_types::MESH_FLAGS_SHADOW_RECEIVER_BIT;
+
+    var out = pbr_functions::apply_pbr_lighting(pbr_input);
+    let lit = max(out.rgb - cloud_shadowed_light(pbr_input, environment), vec3(0.0));
+    var color = common::aerial_perspective(
+        lit,
+        view.world_position.y,
+        normalize(to_fragment),
+        length(to_fragment) * shading.aerial_perspective_scale,
+        environment,
+    );
+    color = common::fog(color, length(to_fragment), environment);
+    return pbr_functions::main_pass_post_lighting_processing(pbr_input, vec4(color, out.a));
+}
@@ -0,0 +1,34 @@
+// Vertex stage of the terrain's prepass with `--pbr`, for the shadows and depth to follow
+// the displaced terrain. Bevy's prepass fragment shader takes it from here.
+
+#import bevy_pbr::{
+    prepass_io,
+    view_transformations::position_world_to_clip,
+}
+
+@vertex
+fn vertex(in: prepass_io::Vertex) -> prepass_io::VertexOutput {
+    var out: prepass_io::VertexOutput;
+    let vertex = terrain_vertex::displace(in.instance_index, in.position);
+    out.world_position = vec4(vertex.world_pos, 1.0);
+    out.position = position_world_to_clip(vertex.world_pos);
+#ifdef UNCLIPPED_DEPTH_ORTHO_EMULATION
+    out.unclipped_depth = out.position.z;
+    // Clamped to avoid clipping, the fragment stage writes the unclipped depth
+    out.position.z = min(out.position.z, 1.0);
+#endif
+
+#ifdef NORMAL_PREPASS_OR_DEFERRED_PREPASS
+    out.world_normal = normalize(vec3(-vertex.slope.x, 1.0, -vertex.slope.y));
+#endif
+
+#ifdef MOTION_VECTOR_PREPASS
+    // The terrain stays in place, but for the slow morphs between LODs
+    out.previous_world_position = out.world_position;
+#endif
+
+#ifdef VERTEX_OUTPUT_INSTANCE_INDEX
+    out.instance_index = in.instance_index;
+#endif
+    return out;
+}
@@ -0,0 +1,79 @@
+#define_import_path terrain_vertex
+
+#import bevy_pbr::mesh_functions
+
+// Displacement of the chunks shared by the vertex stages of `terrain.wgsl` and
+// `terrain_prepass.wgsl`
+
+// `LodUniform` in Rust code
+struct LodUniform {
+    subdivisions: u32,
+    root_chunk_size: f32,
+    range_per_spacing: f32,
+    // Highest the terrain can reach
+    max_height: f32,
+}
+
+// See `TerrainMaterial` in Rust code
+@group(2) @binding(102) var<uniform> lod: LodUniform;
+// Shared with the fragment stages of `terrain.wgsl` and `terrain_pbr.wgsl`
+@group(2) @binding(103) var<uniform> environment: common::Environment;
+
+// Fraction of the parent chunk's LOD range after which chunks start morphing into it
+const morph_start = 0.7;
+
+// Into the fragment stages of `terrain.wgsl` and `terrain_pbr.wgsl`
+struct VertexOutput {
+    @builtin(position) clip_pos: vec4<f32>,
+    @location(0) world_pos: vec3<f32>,
+}
+
+struct DisplacedVertex {
+    world_pos: vec3<f32>,
+    // Of the terrain as displaced, which differs from `terrain_noise::noise` while morphing
+    slope: vec2<f32>,
+}
+
+// Moves the vertex at `local_pos` in the chunk onto the terrain, morphing it into the next
+// coarser LOD with the distance
+fn displace(instance_index: u32, local_pos: vec3<f32>) -> DisplacedVertex {
+    let world_from_local = mesh_functions::get_world_from_local(instance_index);
+    var world_pos = mesh_functions::mesh_position_local_to_world(world_from_local, vec4(local_pos, 1.0)).xyz;
+    var noise = terrain_noise::noise(world_pos.xz);
+
+    let chunk_size = length(world_from_local[0].xyz);
+    if chunk_size < lod.root_chunk_size {
+        let spacing = chunk_size / f32(lod.subdivisions);
+        // Distance below which the parent chunk is split into this one
+        let parent_range = 2.0 * spacing * lod.range_per_spacing;
+        // The main camera's, for all passes to morph alike
+        let dist = distance(vec3(world_pos.x, noise.x, world_pos.z), environment.camera_position);
+        let morph = clamp((dist / parent_range - morph_start) / (1.0 - morph_start), 0.0, 1.0);
+        if morph > 0.0 {
+            noise = mix(noise, coarse_noise(world_pos.xz, local_pos.xz, noise, spacing), morph);
+        }
+    }
+
+    // Skirt vertices start below zero
+    world_pos.y += noise.x;
+    return DisplacedVertex(world_pos, noise.yz);
+}
+
+// `noise` as interpolated by the parent chunk's mesh, which has twice the vertex spacing.
+// `center` is `terrain_noise::noise(pos)`.
+fn coarse_noise(pos: vec2<f32>, local_pos: vec2<f32>, center: vec3<f32>, spacing: f32) -> vec3<f32> {
+    let grid_pos = vec2<u32>(round((local_pos + 0.5) * f32(lod.subdivisions)));
+    let odd = grid_pos % 2 == vec2(1u);
+    if odd.x && odd.y {
+        // Center of a parent quad, which lies on its diagonal (see `chunk_mesh` in Rust code)
+        return (terrain_noise::noise(pos + vec2(spacing, -spacing)) + terrain_noise::noise(pos + vec2(-spacing, spacing))) * 0.5;
+    }
+    if odd.x {
+        return (terrain_noise::noise(pos + vec2(spacing, 0.0)) + terrain_noise::noise(pos - vec2(spacing, 0.0))) * 0.5;
+    }
+    if odd.y {
+        return (terrain_noise::noise(pos + vec2(0.0, spacing)) + terrain_noise::noise(pos - vec2(0.0, spacing))) * 0.5;
+    }
+    // Also a vertex of the parent
+    return center;
+}
@@ -14,12 +14,12 @@
 @group(0) @binding(0) var depth_texture: texture_depth_multisampled_2d;
 @group(0) @binding(1) var texture: texture_2d<f32>;
 @group(0) @binding(2) var texture_sampler: sampler;
+@group(0) @binding(32) var<uniform> environment: common::Environment;
 
 const see_dist = 100.0;
 const falloff = 0.5;
 const color = vec3(0.0, 0.2, 0.6);
 
-const reflection_color = (color + common::grass_color) * 0.5;
 const reflection_ray_len = 10.0; // keep below see_dist for underwater reflections
 const reflection_blend_size = 0.1;
 
@@ -53,10 +53,13 @@ fn main(in: FullscreenVertexOutput) -> @location(0) vec4<f32> {
     }
 
     let intensity = water_depth_to_intensity(water_depth);
-    let brightness = brightness();
+    // Where the light falling on the water is seen, for the shadows of the clouds
+    let hits_surface = surface_dist > 0.0 && surface_dist < cam_terrain_dist;
+    let lit_pos = select(vec3(pos.x, 0.0, pos.z), surface_pos, hits_surface);
+    let brightness = brightness(lit_pos);
     let with_water_color = mix(in_color.rgb, color * brightness, intensity);
 
-    if surface_dist <= 0.0 || surface_dist >= cam_terrain_dist {
+    if !hits_surface {
         return vec4(with_water_color, 1.0);
     }
 
@@ -72,25 +75,34 @@ fn main(in: FullscreenVertexOutput) -> @location(0) vec4<f32> {
     return vec4(with_reflection, 1.0);
 }
 
-fn brightness() -> f32 {
-    let sun_dir = common::sun_dir(globals.time);
-    let moon_dir = common::moon_dir(sun_dir);
-    let sun_height = common::map_sky_height(sun_dir.y);
-    let moon_height = common::map_sky_height(moon_dir.y);
-    return common::sky_brightness(sun_height, moon_height);
+// Per light, for the shadows of the clouds
+const cloud_shadow_samples = 3;
+
+fn brightness(pos: vec3<f32>) -> f32 {
+    let sun_dir = environment.sun_dir;
+    let moon_dir = environment.moon_dir;
+    let sun_shadow = common::cloud_transmittance(pos, sun_dir, globals.time, cloud_shadow_samples, environment);
+    let moon_shadow = common::cloud_transmittance(pos, moon_dir, globals.time, cloud_shadow_samples, environment);
+    let sun_height = common::map_sky_height(sun_dir.y) * sun_shadow;
+    let moon_height = common::map_sky_height(moon_dir.y) * moon_shadow;
+    return common::sky_brightness(sun_height, moon_height, environment.moon_illumination);
 }
 
 const wave_octaves = 5;
 const speed = 1.0;
+// Distinct from the terrain octaves
+const wave_salt = 0x3a7e0u;
 
 fn normal(pos: vec2<f32>, from_above: bool) -> vec3<f32> {
     var sum = vec2(0.0);
     var freq = 0.1;
     var amp = 0.5;
-    var angle = 0.0;
+    // Random but fixed directions and phases for each seed
+    var angle = common::seed_offset(environment.seed, wave_salt).x;
     for (var i = 0; i < wave_octaves; i++) {
         let dir = vec2(cos(angle), sin(angle));
-        sum += cos(globals.time * speed + dot(pos, dir) * freq) * amp * dir * freq;
+        let phase = common::seed_offset(environment.seed, wave_salt + u32(i) + 1u).x;
+        sum += cos(globals.time * speed + dot(pos, dir) * freq + phase) * amp * dir * freq * environment.wave_strength;
         freq *= 2.0;
         amp *= 0.5;
         angle += 1.0;
@@ -117,7 +129,8 @@ fn reflection(surface_pos: vec3<f32>, normal: vec3<f32>, ray_dir: vec3<f32>, bri
     let ndc = clip.xy / clip.w;
     let uv = ndc_to_uv(ndc);
 
-    let base_color = reflection_color * brightness;
+    // Where the reflection leaves the screen, halfway between the water and the shore
+    let base_color = (color + environment.grass_color) * 0.5 * brightness;
 
     if uv.x < 0.0 || uv.x >= 1.0 || uv.y < 0.0 || uv.y >= 1.0 {
         return base_color;
@@ -0,0 +1,306 @@
+//! Biomes, picked by temperature and moisture varying slowly across the world, and by altitude.
+//!
+//! Mirrored in `terrain_biomes.wgsl`, keep both in sync.
+
+use bevy::prelude::*;
+
+use crate::{
+    height_field::{TerrainNoiseSettings, simplex_noise_2d_derivative},
+    seed::seed_offset,
+};
+
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum Biome {
+    Grassland,
+    Desert,
+    Tundra,
+    Forest,
+    Swamp,
+    Alpine,
+    Beach,
+}
+
+/// The biomes following from the climate alone, which shape the terrain.
+///
+/// Alpine and beach follow from the altitude, so they can't reshape the terrain without
+/// feeding back on themselves, and only color it.
+const CLIMATE_BIOMES: [Biome; 5] = [
+    Biome::Grassland,
+    Biome::Desert,
+    Biome::Tundra,
+    Biome::Forest,
+    Biome::Swamp,
+];
+
+impl Biome {
+    pub const ALL: [Self; 7] = [
+        Self::Grassland,
+        Self::Desert,
+        Self::Tundra,
+        Self::Forest,
+        Self::Swamp,
+        Self::Alpine,
+        Self::Beach,
+    ];
+
+    /// Temperature and moisture, each in `[0, 1]`, the biome is most typical of.
+    fn climate(self) -> Vec2 {
+        match self {
+            Self::Grassland => Vec2::new(0.55, 0.35),
+            Self::Desert => Vec2::new(0.85, 0.15),
+            Self::Tundra => Vec2::new(0.15, 0.45),
+            Self::Forest => Vec2::new(0.45, 0.7),
+            Self::Swamp => Vec2::new(0.8, 0.85),
+            Self::Alpine | Self::Beach => Vec2::ZERO,
+        }
+    }
+
+    /// Multiplies [`TerrainNoiseSettings::amplitude`].
+    const fn amplitude(self) -> f32 {
+        match self {
+            Self::Grassland => 1.0,
+            Self::Desert => 0.5,
+            Self::Tundra => 1.4,
+            Self::Forest => 1.2,
+            Self::Swamp => 0.3,
+            Self::Alpine | Self::Beach => 1.0,
+        }
+    }
+
+    /// Multiplies [`TerrainNoiseSettings::gain`], so higher is more rugged.
+    const fn roughness(self) -> f32 {
+        match self {
+            Self::Grassland => 1.0,
+            Self::Desert => 0.85,
+            Self::Tundra => 1.05,
+            Self::Forest => 1.0,
+            Self::Swamp => 0.7,
+            Self::Alpine | Self::Beach => 1.0,
+        }
+    }
+}
+
+/// [`Biome::amplitude`] of each of the [`CLIMATE_BIOMES`].
+const CLIMATE_AMPLITUDES: [f32; CLIMATE_BIOMES.len()] = {
+    let mut amplitudes = [0.0; CLIMATE_BIOMES.len()];
+    let mut i = 0;
+    while i < CLIMATE_BIOMES.len() {
+        amplitudes[i] = CLIMATE_BIOMES[i].amplitude();
+        i += 1;
+    }
+    amplitudes
+};
+/// [`Biome::roughness`] of each of the [`CLIMATE_BIOMES`].
+const CLIMATE_ROUGHNESSES: [f32; CLIMATE_BIOMES.len()] = {
+    let mut roughnesses = [0.0; CLIMATE_BIOMES.len()];
+    let mut i = 0;
+    while i < CLIMATE_BIOMES.len() {
+        roughnesses[i] = CLIMATE_BIOMES[i].roughness();
+        i += 1;
+    }
+    roughnesses
+};
+
+/// Lowest [`Biome::amplitude`], bounding the terrain height.
+///
+/// [`biome_shape`] blends the climate biomes, never leaving the range of their own shapes.
+pub const MIN_BIOME_AMPLITUDE: f32 = extremes(CLIMATE_AMPLITUDES).0;
+/// Highest [`Biome::amplitude`], bounding the terrain height.
+pub const MAX_BIOME_AMPLITUDE: f32 = extremes(CLIMATE_AMPLITUDES).1;
+/// Highest [`Biome::roughness`], bounding the terrain height.
+pub const MAX_BIOME_ROUGHNESS: f32 = extremes(CLIMATE_ROUGHNESSES).1;
+
+/// Smallest and largest of `values`.
+const fn extremes(values: [f32; CLIMATE_BIOMES.len()]) -> (f32, f32) {
+    let (mut min, mut max) = (f32::INFINITY, f32::NEG_INFINITY);
+    let mut i = 0;
+    while i < values.len() {
+        min = min.min(values[i]);
+        max = max.max(values[i]);
+        i += 1;
+    }
+    (min, max)
+}
+
+/// How far apart in temperature and moisture biomes blend into each other.
+const CLIMATE_BLEND: f32 = 0.12;
+/// Altitude above which the terrain turns alpine.
+const ALPINE_HEIGHT: f32 = 50.0;
+const ALPINE_BLEND: f32 = 10.0;
+/// Altitude below which the terrain turns into beach, down into the water to `-BEACH_DEPTH`.
+const BEACH_HEIGHT: f32 = 1.0;
+const BEACH_DEPTH: f32 = 1.0;
+const BEACH_BLEND: f32 = 1.0;
+
+// Distinct from the terrain octaves
+const TEMPERATURE_SALT: u32 = 0x7e4a0;
+const MOISTURE_SALT: u32 = 0x3015e;
+/// Shrinks the seed's offset into the climate noise. At its low frequency, the full offset
+/// leaves too little precision for the climate to change smoothly from one step to the next.
+const CLIMATE_OFFSET_SCALE: f32 = 1.0 / 64.0;
+
+/// How much of each biome there is at a point, summing to 1. Indexed by `Biome as usize`.
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub struct BiomeWeights(pub [f32; Biome::ALL.len()]);
+
+impl BiomeWeights {
+    pub fn weight(&self, biome: Biome) -> f32 {
+        self.0[biome as usize]
+    }
+
+    /// The biome with the largest weight.
+    pub fn dominant(&self) -> Biome {
+        Biome::ALL
+            .into_iter()
+            .max_by(|a, b| self.weight(*a).total_cmp(&self.weight(*b)))
+            .unwrap_or(Biome::Grassland)
+    }
+}
+
+/// Temperature and moisture at a point, each in `[0, 1]`, with their gradients.
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub struct Climate {
+    pub value: Vec2,
+    pub temperature_gradient: Vec2,
+    pub moisture_gradient: Vec2,
+}
+
+impl Climate {
+    /// Gradient of a function of the climate, given its derivatives by temperature and moisture.
+    fn chain(&self, derivative: Vec2) -> Vec2 {
+        derivative.x * self.temperature_gradient + derivative.y * self.moisture_gradient
+    }
+}
+
+pub fn climate_at(noise: &TerrainNoiseSettings, pos: Vec2) -> Climate {
+    let field = |salt: u32| {
+        let p =
+            pos * noise.climate_frequency + seed_offset(noise.seed, salt) * CLIMATE_OFFSET_SCALE;
+        let (a, b) = (
+            simplex_noise_2d_derivative(p),
+            simplex_noise_2d_derivative(p * 2.0),
+        );
+        let n = (a.x + 0.5 * b.x) / 1.5;
+        // Stretched, as the extremes of the noise are rare
+        let value = 0.5 + 0.7 * n;
+        let gradient = 0.7 * (a.yz() + b.yz()) / 1.5 * noise.climate_frequency;
+        if (0.0..=1.0).contains(&value) {
+            (value, gradient)
+        } else {
+            (value.clamp(0.0, 1.0), Vec2::ZERO)
+        }
+    };
+    let (temperature, temperature_gradient) = field(TEMPERATURE_SALT);
+    let (moisture, moisture_gradient) = field(MOISTURE_SALT);
+    Climate {
+        value: Vec2::new(temperature, moisture),
+        temperature_gradient,
+        moisture_gradient,
+    }
+}
+
+/// Weights of [`CLIMATE_BIOMES`], summing to 1, with their derivatives by temperature and
+/// moisture.
+fn climate_weights(climate: Vec2) -> [(f32, Vec2); CLIMATE_BIOMES.len()] {
+    let closeness = CLIMATE_BIOMES.map(|biome| {
+        let offset = climate - biome.climate();
+        let e = (-offset.length_squared() / CLIMATE_BLEND.powi(2)).exp();
+        (e, e * -2.0 * offset / CLIMATE_BLEND.powi(2))
+    });
+    let sum = closeness
+        .iter()
+        .map(|(e, _)| e)
+        .sum::<f32>()
+        .max(f32::MIN_POSITIVE);
+    let sum_derivative: Vec2 = closeness.iter().map(|(_, d)| d).sum();
+    closeness.map(|(e, d)| {
+        let weight = e / sum;
+        // Quotient rule
+        (weight, (d - weight * sum_derivative) / sum)
+    })
+}
+
+/// How the biomes shape the terrain at a point.
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub struct BiomeShape {
+    /// Multiplies [`TerrainNoiseSettings::amplitude`]
+    pub amplitude: f32,
+    /// Multiplies [`TerrainNoiseSettings::gain`]
+    pub roughness: f32,
+    pub amplitude_gradient: Vec2,
+    pub roughness_gradient: Vec2,
+}
+
+pub fn biome_shape(climate: &Climate) -> BiomeShape {
+    let mut shape = BiomeShape {
+        amplitude: 0.0,
+        roughness: 0.0,
+        amplitude_gradient: Vec2::ZERO,
+        roughness_gradient: Vec2::ZERO,
+    };
+    for (biome, (weight, derivative)) in CLIMATE_BIOMES.iter().zip(climate_weights(climate.value)) {
+        let gradient = climate.chain(derivative);
+        shape.amplitude += biome.amplitude() * weight;
+        shape.roughness += biome.roughness() * weight;
+        shape.amplitude_gradient += biome.amplitude() * gradient;
+        shape.roughness_gradient += biome.roughness() * gradient;
+    }
+    shape
+}
+
+/// Weights of all biomes for the given climate and altitude.
+pub fn biome_weights(climate: Vec2, altitude: f32) -> BiomeWeights {
+    let smoothstep = |edge0: f32, edge1: f32, x: f32| {
+        let t = ((x - edge0) / (edge1 - edge0)).clamp(0.0, 1.0);
+        t * t * (3.0 - 2.0 * t)
+    };
+    let alpine = smoothstep(
+        ALPINE_HEIGHT - ALPINE_BLEND,
+        ALPINE_HEIGHT + ALPINE_BLEND,
+        altitude,
+    );
+    let beach = (1.0 - smoothstep(BEACH_HEIGHT, BEACH_HEIGHT + BEACH_BLEND, altitude))
+        * smoothstep(-BEACH_DEPTH - BEACH_BLEND, -BEACH_DEPTH, altitude)
+        * (1.0 - alpine);
+    let mut weights = [0.0; Biome::ALL.len()];
+    for (biome, (weight, _)) in CLIMATE_BIOMES.iter().zip(climate_weights(climate)) {
+        weights[*biome as usize] = weight * (1.0 - alpine - beach);
+    }
+    weights[Biome::Alpine as usize] = alpine;
+    weights[Biome::Beach as usize] = beach;
+    BiomeWeights(weights)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn weights_sum_to_one() {
+        for altitude in [-5.0, -1.5, 0.0, 1.5, 20.0, 45.0, 55.0, 100.0] {
+            for i in 0..=100 {
+                let climate = Vec2::new((i % 11) as f32, (i / 11) as f32) / 10.0;
+                let sum: f32 = biome_weights(climate, altitude).0.iter().sum();
+                assert!(
+                    (sum - 1.0).abs() < 1e-5,
+                    "{sum} at {climate} and altitude {altitude}"
+                );
+            }
+        }
+    }
+
+    #[test]
+    fn typical_climate_picks_its_biome() {
+        for biome in CLIMATE_BIOMES {
+            assert_eq!(biome_weights(biome.climate(), 20.0).dominant(), biome);
+        }
+        assert_eq!(
+            biome_weights(Biome::Grassland.climate(), 100.0).dominant(),
+            Biome::Alpine
+        );
+        assert_eq!(
+            biome_weights(Biome::Grassland.climate(), 0.0).dominant(),
+            Biome::Beach
+        );
+    }
+}
@@ -0,0 +1,127 @@
+//! Positions of the sun and moon in the sky, after the low precision formulas of the
+//! [Astronomical Almanac](https://aa.usno.navy.mil/faq/sun_approx).
+//! Good to a fraction of a degree, which is plenty for a landscape.
+
+use bevy::{
+    math::{DMat3, DVec2, DVec3},
+    prelude::*,
+};
+use serde::Deserialize;
+
+use crate::time_of_day::TimeOfDay;
+
+/// Where on Earth the landscape lies.
+#[derive(Resource, Deserialize, Clone, PartialEq, Debug)]
+pub struct Observer {
+    /// In degrees, positive north of the equator
+    pub latitude: f32,
+}
+
+impl Default for Observer {
+    fn default() -> Self {
+        Self { latitude: 45.0 }
+    }
+}
+
+/// Directions in world space, with X pointing east, Y up and -Z north.
+pub struct CelestialPositions {
+    pub sun_dir: Vec3,
+    pub moon_dir: Vec3,
+    /// Illuminated fraction of the moon's disc, 0 at new moon and 1 at full moon
+    pub moon_illumination: f32,
+    /// Rotates directions in world space into the frame of the celestial sphere,
+    /// with X pointing to the vernal equinox and Z to the celestial north pole.
+    pub equatorial_from_world: Mat3,
+}
+
+/// Obliquity of the ecliptic
+const OBLIQUITY: f64 = 23.439;
+
+impl CelestialPositions {
+    pub fn new(observer: &Observer, time: &TimeOfDay) -> Self {
+        let d = time.days_since_j2000();
+
+        let sun = ecliptic_to_equatorial(sun_ecliptic(d));
+        let moon = ecliptic_to_equatorial(moon_ecliptic(d));
+
+        // The time of day is local solar time, so it gives the sun's hour angle,
+        // from which follows how far the sky has turned.
+        let sun_hour_angle = (time.hour as f64 - 12.0) / 24.0 * std::f64::consts::TAU;
+        let sidereal_time = sun_hour_angle + right_ascension(sun);
+
+        let world_from_equatorial =
+            world_from_equatorial(sidereal_time, (observer.latitude as f64).to_radians());
+        let elongation = sun.dot(moon).clamp(-1.0, 1.0).acos();
+        Self {
+            sun_dir: (world_from_equatorial * sun).as_vec3(),
+            moon_dir: (world_from_equatorial * moon).as_vec3(),
+            moon_illumination: ((1.0 - elongation.cos()) / 2.0) as f32,
+            equatorial_from_world: world_from_equatorial.transpose().as_mat3(),
+        }
+    }
+}
+
+/// Ecliptic longitude and latitude of the sun in radians, `d` days after J2000.
+fn sun_ecliptic(d: f64) -> DVec2 {
+    let mean_longitude = 280.460 + 0.9856474 * d;
+    let mean_anomaly = (357.528 + 0.9856003 * d).to_radians();
+    let longitude =
+        mean_longitude + 1.915 * mean_anomaly.sin() + 0.020 * (2.0 * mean_anomaly).sin();
+    DVec2::new(longitude.to_radians(), 0.0)
+}
+
+/// Ecliptic longitude and latitude of the moon in radians, `d` days after J2000.
+///
+/// Only the largest terms of the lunar theory, ignoring the parallax.
+fn moon_ecliptic(d: f64) -> DVec2 {
+    let mean_longitude = 218.316 + 13.176396 * d;
+    let mean_anomaly = (134.963 + 13.064993 * d).to_radians();
+    let argument_of_latitude = (93.272 + 13.229350 * d).to_radians();
+    DVec2::new(
+        (mean_longitude + 6.289 * mean_anomaly.sin()).to_radians(),
+        (5.128 * argument_of_latitude.sin()).to_radians(),
+    )
+}
+
+/// Unit vector with X pointing to the vernal equinox and Z to the celestial north pole.
+fn ecliptic_to_equatorial(ecliptic: DVec2) -> DVec3 {
+    let (longitude, latitude) = (ecliptic.x, ecliptic.y);
+    let v = DVec3::new(
+        latitude.cos() * longitude.cos(),
+        latitude.cos() * longitude.sin(),
+        latitude.sin(),
+    );
+    let (sin, cos) = OBLIQUITY.to_radians().sin_cos();
+    DVec3::new(v.x, v.y * cos - v.z * sin, v.y * sin + v.z * cos)
+}
+
+fn right_ascension(equatorial: DVec3) -> f64 {
+    equatorial.y.atan2(equatorial.x)
+}
+
+/// Turns the celestial sphere to the sidereal time and tilts it to the latitude.
+fn world_from_equatorial(sidereal_time: f64, latitude: f64) -> DMat3 {
+    let (sin_lat, cos_lat) = latitude.sin_cos();
+    // From the meridian (X pointing away from the pole, Y east, Z to the pole)
+    // to world space (X east, Y up, Z south)
+    let world_from_meridian = DMat3::from_cols(
+        DVec3::new(0.0, cos_lat, sin_lat),
+        DVec3::X,
+        DVec3::new(0.0, sin_lat, -cos_lat),
+    );
+    world_from_meridian * DMat3::from_rotation_z(-sidereal_time)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn sun_is_up_at_noon_on_the_solstice() {
+        let positions = CelestialPositions::new(&Observer::default(), &TimeOfDay::default());
+        // About 90° - 45° + 23.4° high, due south
+        let altitude = positions.sun_dir.y.asin().to_degrees();
+        assert!((altitude - 68.4).abs() < 1.0, "altitude: {altitude}");
+        assert!(positions.sun_dir.z > 0.0);
+    }
+}
@@ -0,0 +1,122 @@
+use bevy::{prelude::*, window::WindowMode};
+use clap::{Parser, ValueEnum};
+
+use crate::{time_of_day::TimeOfDay, weather::WeatherKind};
+
+/// A procedural landscape.
+///
+/// Options given here take precedence over the world settings file.
+#[derive(Parser, Resource, Clone, Debug)]
+#[command(version)]
+pub struct Cli {
+    /// World seed, overriding the one in the world settings
+    #[arg(long)]
+    pub seed: Option<u32>,
+    /// Starting camera position, defaults to just above the ground at the origin
+    #[arg(long, value_names = ["X", "Y", "Z"], num_args = 3, allow_negative_numbers = true)]
+    pub position: Option<Vec<f32>>,
+    /// Starting camera yaw in degrees, counterclockwise from looking along -Z
+    #[arg(long, default_value_t = 0.0, allow_negative_numbers = true)]
+    pub yaw: f32,
+    /// Starting camera pitch in degrees, positive looking up
+    #[arg(long, default_value_t = 0.0, allow_negative_numbers = true)]
+    pub pitch: f32,
+    /// Render distance in root chunks, overriding the one in the world settings
+    #[arg(long, value_parser = clap::value_parser!(i32).range(0..))]
+    pub render_dist: Option<i32>,
+    /// Initial window mode, can be toggled in the pause menu
+    #[arg(long, value_enum, default_value_t = WindowModeArg::Windowed)]
+    pub window_mode: WindowModeArg,
+    /// Starting local solar time in hours, 12 being noon
+    #[arg(long)]
+    pub time_of_day: Option<f32>,
+    /// Starting day of the year, 1 being January 1
+    #[arg(long, value_parser = clap::value_parser!(u32).range(1..=366))]
+    pub day_of_year: Option<u32>,
+    /// Starting year, which only matters for the phase of the moon
+    #[arg(long, allow_negative_numbers = true)]
+    pub year: Option<i32>,
+    /// Latitude in degrees, positive north of the equator, overriding the one in the world settings
+    #[arg(long, allow_negative_numbers = true, value_parser = parse_latitude)]
+    pub latitude: Option<f32>,
+    /// Don't render the sky, leaving the clear color behind the terrain
+    #[arg(long)]
+    pub no_sky: bool,
+    /// Don't render the water
+    #[arg(long)]
+    pub no_water: bool,
+    /// Starting weather, which changes over time unless the world settings say otherwise
+    #[arg(long, value_enum)]
+    pub weather: Option<WeatherKind>,
+    /// Don't render clouds or their shadows
+    #[arg(long)]
+    pub no_clouds: bool,
+    /// Shade the terrain with Bevy's PBR lighting, lit by directional lights for the sun and moon
+    #[arg(long)]
+    pub pbr: bool,
+    /// Leave the Milky Way out of the night sky. Has no effect with `--no-sky`
+    #[arg(long)]
+    pub no_milky_way: bool,
+}
+
+#[derive(ValueEnum, Clone, Copy, Debug)]
+pub enum WindowModeArg {
+    Windowed,
+    Borderless,
+    Fullscreen,
+}
+
+impl From<WindowModeArg> for WindowMode {
+    fn from(mode: WindowModeArg) -> Self {
+        match mode {
+            WindowModeArg::Windowed => WindowMode::Windowed,
+            WindowModeArg::Borderless => {
+                WindowMode::BorderlessFullscreen(MonitorSelection::Current)
+            }
+            WindowModeArg::Fullscreen => {
+                WindowMode::Fullscreen(MonitorSelection::Current, VideoModeSelection::Current)
+            }
+        }
+    }
+}
+
+fn parse_latitude(s: &str) -> Result<f32, String> {
+    let latitude: f32 = s.parse().map_err(|e| format!("{e}"))?;
+    if (-90.0..=90.0).contains(&latitude) {
+        Ok(latitude)
+    } else {
+        Err("must be between -90 and 90".into())
+    }
+}
+
+impl Cli {
+    pub fn time_of_day(&self) -> TimeOfDay {
+        let default = TimeOfDay::default();
+        let mut time_of_day = TimeOfDay {
+            hour: self.time_of_day.unwrap_or(default.hour).rem_euclid(24.0),
+            year: self.year.unwrap_or(default.year),
+            ..default
+        };
+        if let Some(day) = self.day_of_year {
+            time_of_day.day = day - 1;
+            // Day 366 of a common year is January 1 of the next
+            time_of_day.advance(0.0);
+        }
+        time_of_day
+    }
+
+    pub fn cam_transform(&self, ground_height: f32) -> Transform {
+        let translation = match self.position.as_deref() {
+            Some(&[x, y, z]) => Vec3::new(x, y, z),
+            // Start above the ground instead of inside it
+            _ => Vec3::new(0.0, ground_height + 2.0, 0.0),
+        };
+        let rotation = Quat::from_euler(
+            EulerRot::YXZ,
+            self.yaw.to_radians(),
+            self.pitch.to_radians(),
+            0.0,
+        );
+        Transform::from_translation(translation).with_rotation(rotation)
+    }
+}
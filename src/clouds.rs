@@ -0,0 +1,41 @@
+use bevy::{
+    core_pipeline::core_3d::graph::Core3d,
+    prelude::*,
+    render::{
+        RenderApp,
+        render_graph::{RenderGraph, RenderLabel},
+    },
+};
+
+use crate::{
+    RenderWaterLabel,
+    fullscreen::{PostProcess, add_post_process},
+};
+
+/// Raymarches the cloud layer described by [`crate::environment::CloudSettings`] over the
+/// rendered view.
+pub struct CloudsPlugin;
+
+impl Plugin for CloudsPlugin {
+    fn build(&self, _app: &mut App) {}
+
+    fn finish(&self, app: &mut App) {
+        let render_app = app.get_sub_app_mut(RenderApp).expect("No RenderApp");
+        add_post_process::<RenderCloudsLabel>(render_app);
+        // Before the water, so that it reflects the clouds
+        let mut render_graph = render_app.world_mut().resource_mut::<RenderGraph>();
+        if let Some(graph) = render_graph.get_sub_graph_mut(Core3d)
+            && graph.get_node_state(RenderWaterLabel).is_ok()
+        {
+            graph.add_node_edge(RenderCloudsLabel, RenderWaterLabel);
+        }
+    }
+}
+
+#[derive(RenderLabel, Hash, Debug, PartialEq, Eq, Clone, Default)]
+struct RenderCloudsLabel;
+
+impl PostProcess for RenderCloudsLabel {
+    const NAME: &'static str = "clouds";
+    const SHADER: &'static str = "shaders/clouds.wgsl";
+}
@@ -0,0 +1,272 @@
+use bevy::{
+    prelude::*,
+    render::{
+        Render, RenderApp, RenderSet,
+        extract_resource::{ExtractResource, ExtractResourcePlugin},
+        render_resource::{ShaderType, UniformBuffer},
+        renderer::{RenderDevice, RenderQueue},
+    },
+};
+use serde::Deserialize;
+
+use crate::{
+    TerrainShading,
+    celestial::{CelestialPositions, Observer},
+    seed::WorldSeed,
+    time_of_day::TimeOfDay,
+    weather::Weather,
+};
+
+pub use uniform::EnvironmentUniform;
+
+/// Binding of [`EnvironmentUniform`] in the sky, water and cloud bind groups,
+/// clear of the ones Bevy's shader imports assume.
+pub const ENVIRONMENT_BINDING: u32 = 32;
+
+/// Gathers the state of the world the shaders share into [`EnvironmentUniform`]
+/// and uploads it for the terrain and the fullscreen passes (sky, water and clouds).
+pub struct EnvironmentPlugin;
+
+impl Plugin for EnvironmentPlugin {
+    fn build(&self, app: &mut App) {
+        app.init_resource::<WorldSeed>()
+            .init_resource::<Observer>()
+            .init_resource::<CloudSettings>()
+            .init_resource::<CloudsEnabled>()
+            .init_resource::<TerrainShading>()
+            .init_resource::<EnvironmentUniform>()
+            .add_plugins(ExtractResourcePlugin::<EnvironmentUniform>::default())
+            .add_systems(PostUpdate, update_environment);
+    }
+
+    fn finish(&self, app: &mut App) {
+        app.get_sub_app_mut(RenderApp)
+            .expect("No RenderApp")
+            .init_resource::<EnvironmentBuffer>()
+            .add_systems(
+                Render,
+                prepare_environment_buffer.in_set(RenderSet::PrepareResources),
+            );
+    }
+}
+
+#[expect(dead_code, reason = "unused size checks of `#[derive(ShaderType)]`")]
+mod uniform {
+    use super::*;
+
+    /// `Environment` in `common.wgsl`.
+    ///
+    /// The terrain binds the same buffer next to its material's bindings.
+    #[derive(Resource, ExtractResource, ShaderType, Clone, Copy, Default, PartialEq, Debug)]
+    pub struct EnvironmentUniform {
+        pub sun_dir: Vec3,
+        pub seed: u32,
+        pub moon_dir: Vec3,
+        /// See [`CelestialPositions::moon_illumination`]
+        pub moon_illumination: f32,
+        /// See [`CelestialPositions::equatorial_from_world`]
+        pub equatorial_from_world: Mat3,
+        pub cloud_bottom: f32,
+        pub cloud_top: f32,
+        /// See [`crate::weather::WeatherConditions::cloud_coverage`]
+        pub cloud_coverage: f32,
+        pub cloud_density: f32,
+        pub cloud_scale: f32,
+        pub wind: Vec2,
+        /// See [`crate::weather::WeatherConditions::fog_density`]
+        pub fog_density: f32,
+        /// See [`crate::weather::WeatherConditions::wave_strength`]
+        pub wave_strength: f32,
+        /// See [`crate::TerrainShading`], tinting the water's reflection of the shore
+        pub grass_color: Vec3,
+        /// Of the sun- and moonlight reaching the camera's height, see [`light_transmittance`]
+        pub sun_transmittance: Vec3,
+        pub moon_transmittance: Vec3,
+        /// Of the sun- and moonlight reaching the ground, lighting the fog
+        pub ground_sun_transmittance: Vec3,
+        pub ground_moon_transmittance: Vec3,
+        /// Of the main camera, which the terrain morphs its LODs by. The shadow and prepass
+        /// views have their own positions, but have to morph the terrain like the main pass.
+        pub camera_position: Vec3,
+    }
+}
+
+/// Shape of the cloud layer, shared by everything the clouds darken.
+#[derive(Resource, Deserialize, Clone, PartialEq, Debug)]
+pub struct CloudSettings {
+    /// Altitude of the cloud bases
+    pub bottom: f32,
+    /// Altitude of the cloud tops
+    pub top: f32,
+    /// Extinction per unit of distance inside the thickest clouds
+    pub density: f32,
+    /// Frequency of the cloud noise, lower values give bigger clouds
+    pub scale: f32,
+    /// Drift of the clouds in units per second
+    pub wind: Vec2,
+}
+
+/// Whether the clouds are drawn, set from [`crate::cli::Cli::no_clouds`]. Clouds that aren't drawn
+/// don't cast shadows either.
+#[derive(Resource, Clone, Copy)]
+pub struct CloudsEnabled(pub bool);
+
+impl Default for CloudsEnabled {
+    fn default() -> Self {
+        Self(true)
+    }
+}
+
+impl Default for CloudSettings {
+    fn default() -> Self {
+        Self {
+            bottom: 150.0,
+            top: 400.0,
+            density: 0.04,
+            scale: 0.003,
+            wind: Vec2::new(8.0, 4.0),
+        }
+    }
+}
+
+impl CloudSettings {
+    /// Checks the settings loaded from the world settings, which the cloud marching relies on.
+    pub fn validate(&self) -> Result<(), String> {
+        for (name, value) in [
+            ("bottom", self.bottom),
+            ("top", self.top),
+            ("density", self.density),
+            ("scale", self.scale),
+            ("wind.x", self.wind.x),
+            ("wind.y", self.wind.y),
+        ] {
+            if !value.is_finite() {
+                return Err(format!("clouds {name} isn't a finite number: {value}"));
+            }
+        }
+        Ok(())
+    }
+}
+
+pub fn update_environment(
+    seed: Res<WorldSeed>,
+    time_of_day: Res<TimeOfDay>,
+    observer: Res<Observer>,
+    clouds: Res<CloudSettings>,
+    weather: Res<Weather>,
+    clouds_enabled: Res<CloudsEnabled>,
+    shading: Res<TerrainShading>,
+    cam: Single<&Transform, With<Camera>>,
+    mut environment: ResMut<EnvironmentUniform>,
+) {
+    let celestial = CelestialPositions::new(&observer, &time_of_day);
+    // The same for every pixel, so not worth marching through the atmosphere for each
+    let height = cam.translation.y;
+    environment.set_if_neq(EnvironmentUniform {
+        sun_dir: celestial.sun_dir,
+        seed: seed.0,
+        moon_dir: celestial.moon_dir,
+        moon_illumination: celestial.moon_illumination,
+        equatorial_from_world: celestial.equatorial_from_world,
+        cloud_bottom: clouds.bottom,
+        cloud_top: clouds.top,
+        cloud_coverage: if clouds_enabled.0 {
+            weather.conditions.cloud_coverage
+        } else {
+            0.0
+        },
+        cloud_density: clouds.density,
+        cloud_scale: clouds.scale,
+        wind: clouds.wind,
+        fog_density: weather.conditions.fog_density,
+        wave_strength: weather.conditions.wave_strength,
+        grass_color: shading.grass_color.to_vec3(),
+        sun_transmittance: light_transmittance(height, celestial.sun_dir),
+        moon_transmittance: light_transmittance(height, celestial.moon_dir),
+        ground_sun_transmittance: light_transmittance(0.0, celestial.sun_dir),
+        ground_moon_transmittance: light_transmittance(0.0, celestial.moon_dir),
+        camera_position: cam.translation,
+    });
+}
+
+/// Relative to the sun's brightness, at full moon. Identical to `common::moon_brightness`.
+pub const MOON_BRIGHTNESS: f32 = 0.3;
+
+/// Identical to `common::map_sky_height` in `common.wgsl`.
+pub fn map_sky_height(dir_y: f32) -> f32 {
+    let y = dir_y.clamp(0.0, 1.0);
+    (y * y * (3.0 - 2.0 * y)).powf(0.3)
+}
+
+/// Identical to `common::sky_brightness` in `common.wgsl`.
+pub fn sky_brightness(
+    mapped_sun_height: f32,
+    mapped_moon_height: f32,
+    moon_illumination: f32,
+) -> f32 {
+    (mapped_sun_height + mapped_moon_height * MOON_BRIGHTNESS * moon_illumination).clamp(0.1, 1.0)
+}
+
+// The atmosphere of `common.wgsl`, keep in sync
+const EARTH_RADIUS: f32 = 6360e3;
+const ATMOSPHERE_RADIUS: f32 = 6420e3;
+const RAYLEIGH_SCATTERING: Vec3 = Vec3::new(5.8e-6, 13.5e-6, 33.1e-6);
+const RAYLEIGH_SCALE_HEIGHT: f32 = 8e3;
+const MIE_EXTINCTION: f32 = 1.1 * 21e-6;
+const MIE_SCALE_HEIGHT: f32 = 1.2e3;
+
+/// Identical to `common::atmosphere_density` in `common.wgsl`.
+fn atmosphere_density(height: f32) -> Vec2 {
+    (-height.max(0.0) / Vec2::new(RAYLEIGH_SCALE_HEIGHT, MIE_SCALE_HEIGHT)).exp()
+}
+
+/// Identical to `common::atmosphere_extinction` in `common.wgsl`.
+fn atmosphere_extinction(density: Vec2) -> Vec3 {
+    RAYLEIGH_SCATTERING * density.x + MIE_EXTINCTION * density.y
+}
+
+/// Identical to `common::atmosphere_ray_length` in `common.wgsl`.
+fn atmosphere_ray_length(origin: Vec3, dir: Vec3) -> f32 {
+    let b = origin.dot(dir);
+    let c = origin.dot(origin);
+    let ground = b * b - c + EARTH_RADIUS * EARTH_RADIUS;
+    if b < 0.0 && ground >= 0.0 {
+        return -b - ground.sqrt();
+    }
+    -b + (b * b - c + ATMOSPHERE_RADIUS * ATMOSPHERE_RADIUS)
+        .max(0.0)
+        .sqrt()
+}
+
+/// Fraction of the light coming from `light_dir` that reaches `height`.
+///
+/// Identical to `common::light_transmittance` in `common.wgsl`.
+pub fn light_transmittance(height: f32, light_dir: Vec3) -> Vec3 {
+    let origin = Vec3::new(0.0, EARTH_RADIUS + height.max(0.0), 0.0);
+    let b = origin.dot(light_dir);
+    if b < 0.0 && b * b - origin.dot(origin) + EARTH_RADIUS * EARTH_RADIUS >= 0.0 {
+        // Below the horizon
+        return Vec3::ZERO;
+    }
+    const SAMPLES: u32 = 8;
+    let step = atmosphere_ray_length(origin, light_dir) / SAMPLES as f32;
+    let mut optical_depth = Vec2::ZERO;
+    for i in 0..SAMPLES {
+        let pos = origin + light_dir * (i as f32 + 0.5) * step;
+        optical_depth += atmosphere_density(pos.length() - EARTH_RADIUS) * step;
+    }
+    (-atmosphere_extinction(optical_depth)).exp()
+}
+
+#[derive(Resource, Default)]
+pub struct EnvironmentBuffer(pub UniformBuffer<EnvironmentUniform>);
+
+fn prepare_environment_buffer(
+    environment: Res<EnvironmentUniform>,
+    mut buffer: ResMut<EnvironmentBuffer>,
+    rd: Res<RenderDevice>,
+    queue: Res<RenderQueue>,
+) {
+    buffer.0.set(*environment);
+    buffer.0.write_buffer(&rd, &queue);
+}
@@ -0,0 +1,229 @@
+//! Passes drawing a single fullscreen triangle: the sky before the terrain, and post-processing
+//! like the water and clouds on top of it.
+
+use std::{borrow::Cow, marker::PhantomData};
+
+use bevy::{
+    core_pipeline::{
+        core_3d::graph::{Core3d, Node3d},
+        fullscreen_vertex_shader::fullscreen_shader_vertex_state,
+    },
+    ecs::{query::QueryItem, system::lifetimeless::Read},
+    prelude::*,
+    render::{
+        Render, RenderSet,
+        globals::{GlobalsBuffer, GlobalsUniform},
+        render_graph::{
+            NodeRunError, RenderGraphApp, RenderGraphContext, RenderLabel, ViewNode, ViewNodeRunner,
+        },
+        render_resource::{
+            BindGroupEntries, BindGroupLayout, BindGroupLayoutEntries, CachedRenderPipelineId,
+            ColorTargetState, ColorWrites, FragmentState, MultisampleState, PipelineCache,
+            RenderPassColorAttachment, RenderPassDescriptor, RenderPipelineDescriptor, Sampler,
+            SamplerBindingType, ShaderDefVal, ShaderStages, SpecializedRenderPipeline,
+            SpecializedRenderPipelines, TextureFormat, TextureSampleType,
+            binding_types::{sampler, texture_2d, texture_2d_multisampled, uniform_buffer},
+        },
+        renderer::{RenderContext, RenderDevice},
+        view::{ViewDepthTexture, ViewTarget, ViewUniform, ViewUniformOffset, ViewUniforms},
+    },
+};
+
+use crate::environment::{ENVIRONMENT_BINDING, EnvironmentBuffer, EnvironmentUniform};
+
+/// Pipeline running the `main` entry point of `shader` over the whole view.
+pub fn fullscreen_pipeline(
+    layout: &BindGroupLayout,
+    shader: &Handle<Shader>,
+    shader_defs: Vec<ShaderDefVal>,
+    multisample: MultisampleState,
+) -> RenderPipelineDescriptor {
+    RenderPipelineDescriptor {
+        label: None,
+        layout: vec![layout.clone()],
+        push_constant_ranges: vec![],
+        vertex: fullscreen_shader_vertex_state(),
+        primitive: default(),
+        depth_stencil: None,
+        multisample,
+        fragment: Some(FragmentState {
+            shader: shader.clone(),
+            shader_defs,
+            entry_point: Cow::Borrowed("main"),
+            targets: vec![Some(ColorTargetState {
+                format: TextureFormat::bevy_default(),
+                blend: None,
+                write_mask: ColorWrites::COLOR,
+            })],
+        }),
+        zero_initialize_workgroup_memory: true,
+    }
+}
+
+/// A fullscreen pass after tonemapping, reading the depth and the rendered view. Its label
+/// names its render graph node.
+pub trait PostProcess: RenderLabel + Default {
+    /// Prefix of the labels of its GPU resources
+    const NAME: &'static str;
+    /// Asset path of the shader, which binds the depth texture at 0, the rendered view at 1
+    /// and its sampler at 2 next to the view, globals and environment
+    const SHADER: &'static str;
+}
+
+/// Adds the pass between tonemapping and the end of post-processing. Called from
+/// [`Plugin::finish`], once the [`RenderDevice`] exists.
+pub fn add_post_process<P: PostProcess>(render_app: &mut SubApp) {
+    render_app
+        .init_resource::<PostProcessPipeline<P>>()
+        .init_resource::<SpecializedRenderPipelines<PostProcessPipeline<P>>>()
+        .add_systems(Render, queue_post_process::<P>.in_set(RenderSet::Queue))
+        .add_render_graph_node::<ViewNodeRunner<PostProcessNode<P>>>(Core3d, P::default())
+        .add_render_graph_edges(
+            Core3d,
+            (
+                Node3d::Tonemapping,
+                P::default(),
+                Node3d::EndMainPassPostProcessing,
+            ),
+        );
+}
+
+#[derive(Resource)]
+struct PostProcessPipeline<P> {
+    shader: Handle<Shader>,
+    layout: BindGroupLayout,
+    sampler: Sampler,
+    marker: PhantomData<P>,
+}
+
+impl<P: PostProcess> FromWorld for PostProcessPipeline<P> {
+    fn from_world(world: &mut World) -> Self {
+        let rd = world.resource::<RenderDevice>();
+        Self {
+            shader: world.load_asset(P::SHADER),
+            layout: rd.create_bind_group_layout(
+                format!("{}_bind_group_layout", P::NAME).as_str(),
+                &BindGroupLayoutEntries::with_indices(
+                    ShaderStages::FRAGMENT,
+                    (
+                        (0, texture_2d_multisampled(TextureSampleType::Depth)),
+                        (
+                            1,
+                            texture_2d(TextureSampleType::Float { filterable: false }),
+                        ),
+                        (2, sampler(SamplerBindingType::NonFiltering)),
+                        (3, uniform_buffer::<ViewUniform>(true)),
+                        (11, uniform_buffer::<GlobalsUniform>(false)),
+                        (
+                            ENVIRONMENT_BINDING,
+                            uniform_buffer::<EnvironmentUniform>(false),
+                        ),
+                    ),
+                ),
+            ),
+            sampler: rd.create_sampler(&default()),
+            marker: PhantomData,
+        }
+    }
+}
+
+impl<P: PostProcess> SpecializedRenderPipeline for PostProcessPipeline<P> {
+    type Key = ();
+
+    fn specialize(&self, _key: Self::Key) -> RenderPipelineDescriptor {
+        fullscreen_pipeline(&self.layout, &self.shader, vec![], default())
+    }
+}
+
+#[derive(Component)]
+struct PostProcessPipelineId<P> {
+    id: CachedRenderPipelineId,
+    marker: PhantomData<P>,
+}
+
+fn queue_post_process<P: PostProcess>(
+    cam: Single<Entity, With<Camera>>,
+    pipeline_cache: Res<PipelineCache>,
+    layouts: Res<PostProcessPipeline<P>>,
+    mut specializer: ResMut<SpecializedRenderPipelines<PostProcessPipeline<P>>>,
+    mut commands: Commands,
+) {
+    let id = specializer.specialize(&pipeline_cache, &layouts, ());
+    commands.entity(*cam).insert(PostProcessPipelineId::<P> {
+        id,
+        marker: PhantomData,
+    });
+}
+
+#[derive(Default)]
+struct PostProcessNode<P>(PhantomData<P>);
+
+impl<P: PostProcess> ViewNode for PostProcessNode<P> {
+    type ViewQuery = (
+        Read<PostProcessPipelineId<P>>,
+        Read<ViewTarget>,
+        Read<ViewDepthTexture>,
+        Read<ViewUniformOffset>,
+    );
+
+    fn run<'w>(
+        &self,
+        _graph: &mut RenderGraphContext,
+        render_context: &mut RenderContext<'w>,
+        (pipeline_id, view_target, view_depth_texture, view_uniform_offset): QueryItem<
+            'w,
+            Self::ViewQuery,
+        >,
+        world: &'w World,
+    ) -> Result<(), NodeRunError> {
+        let pipeline_cache = world.resource::<PipelineCache>();
+        let Some(pipeline) = pipeline_cache.get_render_pipeline(pipeline_id.id) else {
+            return Ok(());
+        };
+
+        let pipeline_specializer = world.resource::<PostProcessPipeline<P>>();
+        let post_process = view_target.post_process_write();
+        let view_bindings = world
+            .resource::<ViewUniforms>()
+            .uniforms
+            .binding()
+            .expect("Could not create view bindings for post-processing bind group");
+        let globals_binding = world
+            .resource::<GlobalsBuffer>()
+            .buffer
+            .binding()
+            .expect("Could not create globals bindings for post-processing bind group");
+        let environment_binding = world
+            .resource::<EnvironmentBuffer>()
+            .0
+            .binding()
+            .expect("Could not create environment bindings for post-processing bind group");
+        let bind_group = render_context.render_device().create_bind_group(
+            format!("{}_bind_group", P::NAME).as_str(),
+            &pipeline_specializer.layout,
+            &BindGroupEntries::with_indices((
+                (0, view_depth_texture.view()),
+                (1, post_process.source),
+                (2, &pipeline_specializer.sampler),
+                (3, view_bindings),
+                (11, globals_binding),
+                (ENVIRONMENT_BINDING, environment_binding),
+            )),
+        );
+
+        let mut pass = render_context
+            .command_encoder()
+            .begin_render_pass(&RenderPassDescriptor {
+                color_attachments: &[Some(RenderPassColorAttachment {
+                    view: post_process.destination,
+                    resolve_target: None,
+                    ops: default(),
+                })],
+                ..default()
+            });
+        pass.set_pipeline(pipeline);
+        pass.set_bind_group(0, &bind_group, &[view_uniform_offset.offset]);
+        pass.draw(0..3, 0..1);
+        Ok(())
+    }
+}
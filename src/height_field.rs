@@ -0,0 +1,414 @@
+use bevy::{prelude::*, render::render_resource::ShaderType};
+use serde::Deserialize;
+
+use crate::{
+    biome::{
+        Biome, BiomeWeights, MAX_BIOME_AMPLITUDE, MAX_BIOME_ROUGHNESS, MIN_BIOME_AMPLITUDE,
+        biome_shape, biome_weights, climate_at,
+    },
+    seed::seed_offset,
+};
+
+pub use settings::TerrainNoiseSettings;
+
+#[expect(dead_code, reason = "unused size checks of `#[derive(ShaderType)]`")]
+mod settings {
+    use super::*;
+
+    /// Parameters of the terrain noise.
+    ///
+    /// Uploaded to `terrain_noise.wgsl` as part of the `TerrainMaterial` and used by
+    /// [`TerrainHeightField`] on the CPU, so both always agree on the terrain shape.
+    #[derive(ShaderType, Deserialize, Clone, Copy, PartialEq, Debug)]
+    pub struct TerrainNoiseSettings {
+        /// Frequency of the first octave. Doubles with every octave.
+        pub frequency: f32,
+        pub octaves: u32,
+        /// Amplitude multiplier between octaves, scaled by the biome's roughness.
+        pub gain: f32,
+        /// Height before adding any noise.
+        pub start_height: f32,
+        /// Vertical scale applied after summing the octaves, scaled by the biome's amplitude.
+        pub amplitude: f32,
+        /// How strongly the accumulated slope dampens later octaves.
+        ///
+        /// <https://youtu.be/gsJHzBTPG0Y>
+        pub slope_falloff: f32,
+        /// Frequency of the temperature and moisture picking the biomes.
+        pub climate_frequency: f32,
+        /// Set from [`crate::seed::WorldSeed`]; each octave samples a region of the noise picked by it.
+        #[serde(skip)]
+        pub seed: u32,
+    }
+}
+
+impl Default for TerrainNoiseSettings {
+    fn default() -> Self {
+        Self {
+            frequency: 0.005,
+            octaves: 9,
+            gain: 0.5,
+            start_height: 0.5,
+            amplitude: 15.0,
+            slope_falloff: 10.0,
+            climate_frequency: 0.0005,
+            seed: 0,
+        }
+    }
+}
+
+impl TerrainNoiseSettings {
+    /// Checks the settings loaded from the world settings, which would make the terrain disappear
+    /// if they weren't numbers.
+    pub fn validate(&self) -> Result<(), String> {
+        for (name, value) in [
+            ("frequency", self.frequency),
+            ("gain", self.gain),
+            ("start_height", self.start_height),
+            ("amplitude", self.amplitude),
+            ("slope_falloff", self.slope_falloff),
+            ("climate_frequency", self.climate_frequency),
+        ] {
+            if !value.is_finite() {
+                return Err(format!("noise {name} isn't a finite number: {value}"));
+            }
+        }
+        Ok(())
+    }
+
+    /// Lowest height the terrain can reach.
+    pub fn min_height(&self) -> f32 {
+        let height = self.transform_height(self.start_height - self.max_noise());
+        // Either amplitude may be the lower, depending on the sign
+        (height * MIN_BIOME_AMPLITUDE).min(height * MAX_BIOME_AMPLITUDE)
+    }
+
+    /// Highest height the terrain can reach.
+    pub fn max_height(&self) -> f32 {
+        let height = self.transform_height(self.start_height + self.max_noise());
+        (height * MIN_BIOME_AMPLITUDE).max(height * MAX_BIOME_AMPLITUDE)
+    }
+
+    /// Upper bound of the summed octaves, before [`Self::transform_height`].
+    fn max_noise(&self) -> f32 {
+        // Sum one octave more than we sample to leave some headroom
+        // for simplex noise slightly exceeding [-1, 1].
+        let gain = self.gain * MAX_BIOME_ROUGHNESS;
+        (0..=self.octaves).map(|i| gain.powi(i as i32)).sum()
+    }
+
+    fn transform_height(&self, height: f32) -> f32 {
+        height.lerp(height * height, sign(height) * 0.5 + 0.5) * self.amplitude
+    }
+
+    fn transform_height_derivative(&self, height: f32) -> f32 {
+        // Power rule
+        1.0f32.lerp(2.0 * height, sign(height) * 0.5 + 0.5) * self.amplitude
+    }
+}
+
+/// CPU port of the `noise` function in `terrain_noise.wgsl`, so Rust code can ask
+/// how high the ground is at a given XZ position.
+///
+/// Always sums all [`TerrainNoiseSettings::octaves`]. The shader's `noise_octaves` also fades in
+/// fractional octave counts for the per pixel slope and the shadows, which the CPU doesn't need.
+///
+/// Keep this in sync with the shader.
+#[derive(Resource, Default, Clone, Copy)]
+pub struct TerrainHeightField {
+    pub noise: TerrainNoiseSettings,
+}
+
+impl TerrainHeightField {
+    /// Height of the terrain surface at `pos`.
+    pub fn height_at(&self, pos: Vec2) -> f32 {
+        self.sample(pos).x
+    }
+
+    /// Partial derivatives of the terrain height along X and Z at `pos`.
+    pub fn slope_at(&self, pos: Vec2) -> Vec2 {
+        self.sample(pos).yz()
+    }
+
+    /// Biome covering most of the terrain at `pos`.
+    pub fn biome_at(&self, pos: Vec2) -> Biome {
+        self.biome_weights_at(pos).dominant()
+    }
+
+    /// How much of each biome there is at `pos`, as blended at the borders.
+    pub fn biome_weights_at(&self, pos: Vec2) -> BiomeWeights {
+        biome_weights(climate_at(&self.noise, pos).value, self.height_at(pos))
+    }
+
+    /// Upward facing surface normal at `pos`.
+    pub fn normal_at(&self, pos: Vec2) -> Vec3 {
+        let slope = self.slope_at(pos);
+        Vec3::new(-slope.x, 1.0, -slope.y).normalize()
+    }
+
+    /// Returns:
+    /// - x: height
+    /// - yz: slope
+    fn sample(&self, pos: Vec2) -> Vec3 {
+        let noise = &self.noise;
+        let shape = biome_shape(&climate_at(noise, pos));
+        let gain = noise.gain * shape.roughness;
+
+        let mut freq = noise.frequency;
+        let mut amp = 1.0;
+        // Derivative of `amp` by `gain`
+        let mut amp_per_gain = 0.0;
+
+        let mut height = noise.start_height;
+        // Of the octaves so far, undamped, to damp the later ones
+        let mut slope = Vec2::ZERO;
+        // Derivatives of `slope` by position and by gain, for those of the damping
+        let mut slope_jacobian = Mat2::ZERO;
+        let mut slope_per_gain = Vec2::ZERO;
+        let mut gradient = Vec2::ZERO;
+        let mut height_per_gain = 0.0;
+
+        for octave in 0..noise.octaves {
+            let offset = seed_offset(noise.seed, octave);
+            let (n, hessian) = simplex_noise_2d_derivatives(pos * freq + offset);
+            // Chain rule
+            slope += n.yz() * freq * amp;
+            slope_jacobian += hessian * (freq * freq * amp);
+            slope_per_gain += n.yz() * freq * amp_per_gain;
+            let damping = 1.0 + noise.slope_falloff * slope.length();
+            let direction = slope.normalize_or_zero();
+            let damping_gradient = noise.slope_falloff * (slope_jacobian * direction);
+            let damping_per_gain = noise.slope_falloff * direction.dot(slope_per_gain);
+            height += n.x * amp / damping;
+            // Quotient rule
+            gradient += (n.yz() * freq * amp * damping - n.x * amp * damping_gradient)
+                / (damping * damping);
+            height_per_gain +=
+                (n.x * amp_per_gain * damping - n.x * amp * damping_per_gain) / (damping * damping);
+            freq *= 2.0;
+            amp_per_gain = amp_per_gain * gain + amp;
+            amp *= gain;
+        }
+        // The roughness changes the gain, and with it the later octaves
+        let gradient = gradient + height_per_gain * noise.gain * shape.roughness_gradient;
+        let transformed = noise.transform_height(height);
+        let slope = gradient * noise.transform_height_derivative(height) * shape.amplitude
+            + transformed * shape.amplitude_gradient;
+        Vec3::new(transformed * shape.amplitude, slope.x, slope.y)
+    }
+}
+
+/// [`noisy_bevy::simplex_noise_2d`] along with its analytic derivatives, in `common.wgsl` on the GPU.
+///
+/// Returns:
+/// - x: noise, the same as [`noisy_bevy::simplex_noise_2d`]
+/// - yz: gradient
+pub fn simplex_noise_2d_derivative(v: Vec2) -> Vec3 {
+    simplex_noise_2d_derivatives(v).0
+}
+
+// MIT License. © Ian McEwan, Stefan Gustavson, Munrocket, Johan Helsing
+/// [`simplex_noise_2d_derivative`] along with the second derivatives (the Hessian).
+fn simplex_noise_2d_derivatives(v: Vec2) -> (Vec3, Mat2) {
+    // Kept step by step like `simplex_noise_2d`, for the noise to match it exactly
+    const C: Vec4 = Vec4::new(
+        0.211_324_87,  // (3.0 - sqrt(3.0)) / 6.0
+        0.366_025_42,  // 0.5 * (sqrt(3.0) - 1.0)
+        -0.577_350_26, // -1.0 + 2.0 * C.x
+        1. / 41.,
+    );
+    let permute = |x: Vec3| (((x * 34.) + 1.) * x) % Vec3::splat(289.);
+
+    let mut i = (v + v.dot(C.yy())).floor();
+    let x0 = v - i + i.dot(C.xx());
+    let i1 = if x0.x > x0.y {
+        Vec2::new(1., 0.)
+    } else {
+        Vec2::new(0., 1.)
+    };
+    let x12 = x0.xyxy() + C.xxzz() - Vec4::new(i1.x, i1.y, 0., 0.);
+    let (x1, x2) = (x12.xy(), x12.zw());
+    i %= Vec2::splat(289.);
+    let p = permute(permute(i.y + Vec3::new(0., i1.y, 1.)) + i.x + Vec3::new(0., i1.x, 1.));
+
+    // Falloff of each corner
+    let t = Vec3::max(
+        0.5 - Vec3::new(x0.dot(x0), x1.dot(x1), x2.dot(x2)),
+        Vec3::ZERO,
+    );
+    let t2 = t * t;
+    let t4 = t2 * t2;
+
+    let x = 2. * (p * C.www()).fract() - 1.;
+    let h = x.abs() - 0.5;
+    let ox = (x + 0.5).floor();
+    let a0 = x - ox;
+    let scale = 1.792_842_9 - 0.853_734_73 * (a0 * a0 + h * h);
+    let m = t4 * scale;
+    let grads = [
+        Vec2::new(a0.x, h.x),
+        Vec2::new(a0.y, h.y),
+        Vec2::new(a0.z, h.z),
+    ];
+    let g = Vec3::new(
+        a0.x * x0.x + h.x * x0.y,
+        a0.y * x1.x + h.y * x1.y,
+        a0.z * x2.x + h.z * x2.y,
+    );
+
+    // Product rule over each corner's falloff t^4 and gradient ramp g, with d(t)/dv = -2 * x
+    let dt4 = -8. * t2 * t * scale * g;
+    let gradient =
+        dt4.x * x0 + dt4.y * x1 + dt4.z * x2 + m.x * grads[0] + m.y * grads[1] + m.z * grads[2];
+
+    // Once more, per corner
+    let outer = |a: Vec2, b: Vec2| Mat2::from_cols(a * b.x, a * b.y);
+    let corners = [x0, x1, x2];
+    let mut hessian = Mat2::ZERO;
+    for k in 0..3 {
+        let x = corners[k];
+        hessian += (outer(x, x) * (48. * t2[k] * g[k])
+            - (outer(x, grads[k]) + outer(grads[k], x) + Mat2::IDENTITY * g[k])
+                * (8. * t2[k] * t[k]))
+            * scale[k];
+    }
+    (
+        130. * Vec3::new(m.dot(g), gradient.x, gradient.y),
+        130. * hessian,
+    )
+}
+
+/// WGSL's `sign`, which unlike [`f32::signum`] returns 0 for 0.
+fn sign(x: f32) -> f32 {
+    if x > 0.0 {
+        1.0
+    } else if x < 0.0 {
+        -1.0
+    } else {
+        0.0
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn field(seed: u32) -> TerrainHeightField {
+        TerrainHeightField {
+            noise: TerrainNoiseSettings { seed, ..default() },
+        }
+    }
+
+    /// Positions spread over a few chunks around the origin.
+    fn grid() -> impl Iterator<Item = Vec2> {
+        (0..1600).map(|i| {
+            Vec2::new(
+                (i % 40) as f32 * 61.3 - 1200.0,
+                (i / 40) as f32 * 57.9 - 1100.0,
+            )
+        })
+    }
+
+    /// Points around the origin, on both sides of it.
+    fn noise_points() -> impl Iterator<Item = Vec2> {
+        (0..2000).map(|i| Vec2::new((i % 45) as f32 * 0.173 - 3.9, (i / 45) as f32 * 0.191 - 4.2))
+    }
+
+    #[test]
+    fn simplex_noise_matches_noisy_bevy() {
+        for v in noise_points() {
+            let noise = simplex_noise_2d_derivative(v).x;
+            assert_eq!(noise, noisy_bevy::simplex_noise_2d(v), "at {v}");
+        }
+    }
+
+    #[test]
+    fn simplex_derivatives_match_finite_differences() {
+        let step = 1e-3;
+        for v in noise_points() {
+            let (noise, hessian) = simplex_noise_2d_derivatives(v);
+            let at = |offset: Vec2| simplex_noise_2d_derivative(v + offset * step);
+            let (x, z) = (
+                (at(Vec2::X) - at(-Vec2::X)) / (2.0 * step),
+                (at(Vec2::Y) - at(-Vec2::Y)) / (2.0 * step),
+            );
+            assert!(
+                (Vec2::new(x.x, z.x) - noise.yz()).length() < 1e-2,
+                "gradient at {v}: {} != {x}, {z}",
+                noise.yz()
+            );
+            assert!(
+                (hessian.x_axis - x.yz()).length() < 1e-2
+                    && (hessian.y_axis - z.yz()).length() < 1e-2,
+                "hessian at {v}: {hessian} != {x}, {z}"
+            );
+        }
+    }
+
+    #[test]
+    fn slope_integrates_to_height() {
+        // Pointwise finite differences can't check all octaves: the seed offsets leave the
+        // noise too little precision for steps short enough to follow the last octaves. Over a
+        // segment, that rounding averages out, and the slope is integrated past the kinks of
+        // `transform_height` and of the damping where the slope vanishes.
+        let len = 1.0;
+        // Even, for Simpson's rule. Several per period of the last octave.
+        let intervals = 64;
+        let h = len / intervals as f32;
+        for seed in [0, 1, 42] {
+            let field = field(seed);
+            for pos in grid() {
+                for dir in [Vec2::X, Vec2::Y] {
+                    let integral = (0..=intervals)
+                        .map(|i| {
+                            let weight = match i {
+                                0 => 1.0,
+                                i if i == intervals => 1.0,
+                                i if i % 2 == 1 => 4.0,
+                                _ => 2.0,
+                            };
+                            weight * field.slope_at(pos + dir * (i as f32 * h)).dot(dir)
+                        })
+                        .sum::<f32>()
+                        * h
+                        / 3.0;
+                    let difference = field.height_at(pos + dir * len) - field.height_at(pos);
+                    // The rounding of the noise coordinates leaves errors of about 0.01,
+                    // a slope off by 2% would be several times that
+                    assert!(
+                        (difference - integral).abs() < 0.02 * len,
+                        "seed {seed} at {pos} along {dir}: {difference} != {integral}"
+                    );
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn height_is_within_bounds() {
+        for seed in [0, 1, 42] {
+            let field = field(seed);
+            for pos in grid() {
+                let height = field.height_at(pos);
+                assert!(height >= field.noise.min_height(), "{height} at {pos}");
+                assert!(height <= field.noise.max_height(), "{height} at {pos}");
+            }
+        }
+    }
+
+    #[test]
+    fn normal_is_unit_and_matches_slope() {
+        for seed in [0, 1, 42] {
+            let field = field(seed);
+            for pos in grid().step_by(7) {
+                let normal = field.normal_at(pos);
+                let slope = field.slope_at(pos);
+                assert!((normal.length() - 1.0).abs() < 1e-5);
+                assert!(normal.y > 0.0);
+                // Perpendicular to the surface's tangents along X and Z
+                assert!(normal.dot(Vec3::new(1.0, slope.x, 0.0)).abs() < 1e-5);
+                assert!(normal.dot(Vec3::new(0.0, slope.y, 1.0)).abs() < 1e-5);
+            }
+        }
+    }
+}
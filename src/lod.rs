@@ -0,0 +1,331 @@
+use bevy::{
+    asset::RenderAssetUsages,
+    platform::collections::HashSet,
+    prelude::*,
+    render::{
+        mesh::{Indices, PrimitiveTopology},
+        render_resource::ShaderType,
+    },
+};
+
+use serde::Deserialize;
+
+pub use uniform::LodUniform;
+
+/// Quads along each side of a chunk, regardless of its LOD.
+pub const CHUNK_SUBDIVISIONS: u32 = 64;
+
+/// How far the skirts reach below the surface, relative to the chunk size.
+///
+/// Skirts hide the cracks between chunks of different LOD.
+/// Deep enough to cover the error of the next coarser LOD.
+pub const SKIRT_DEPTH: f32 = 4.0 / CHUNK_SUBDIVISIONS as f32;
+
+/// Most quadtree levels [`LodSettings::validate`] accepts.
+///
+/// Chunk positions at the finest LOD grow with `2^lod_count` and would overflow long before
+/// that many levels were useful.
+pub const MAX_LOD_COUNT: u8 = 16;
+
+/// How chunks are streamed in around the camera and split into LODs.
+#[derive(Resource, Deserialize, Clone, PartialEq)]
+pub struct LodSettings {
+    /// In root chunks
+    pub render_dist: i32,
+    /// Size of the root chunks, which get split into smaller ones near the camera
+    pub chunk_size: f32,
+    /// Number of quadtree levels below and including the root chunks
+    pub lod_count: u8,
+    /// Largest acceptable geometric error of the terrain, in pixels
+    pub max_screen_space_error: f32,
+}
+
+impl Default for LodSettings {
+    fn default() -> Self {
+        Self {
+            render_dist: 4,
+            chunk_size: 1600.0,
+            lod_count: 8,
+            max_screen_space_error: 4.0,
+        }
+    }
+}
+
+impl LodSettings {
+    /// Checks the settings loaded from the world settings, which the rest of the code relies on.
+    pub fn validate(&self) -> Result<(), String> {
+        if self.render_dist < 0 {
+            return Err(format!("render_dist is negative: {}", self.render_dist));
+        }
+        if !self.chunk_size.is_finite() || self.chunk_size <= 0.0 {
+            return Err(format!(
+                "chunk_size isn't a positive number: {}",
+                self.chunk_size
+            ));
+        }
+        if self.lod_count == 0 {
+            return Err("lod_count is 0, there has to be at least the root chunks".into());
+        }
+        if self.lod_count > MAX_LOD_COUNT {
+            return Err(format!(
+                "lod_count is above {MAX_LOD_COUNT}: {}",
+                self.lod_count
+            ));
+        }
+        if !self.max_screen_space_error.is_finite() || self.max_screen_space_error <= 0.0 {
+            return Err(format!(
+                "max_screen_space_error isn't a positive number: {}",
+                self.max_screen_space_error
+            ));
+        }
+        Ok(())
+    }
+
+    /// Size of chunks with the given LOD, 0 being the most detailed one.
+    pub fn chunk_size(&self, lod: u8) -> f32 {
+        self.chunk_size / 2f32.powi((self.lod_count - 1 - lod) as i32)
+    }
+
+    /// Distance below which a chunk gets split into more detailed ones, divided by its vertex spacing.
+    ///
+    /// `pixels_per_unit` is the height in pixels of something one unit tall,
+    /// one unit away from the camera.
+    pub fn range_per_spacing(&self, pixels_per_unit: f32) -> f32 {
+        // The error is roughly proportional to the vertex spacing
+        pixels_per_unit / self.max_screen_space_error
+    }
+
+    /// Distances below which chunks of each LOD get split into more detailed ones.
+    pub fn ranges(&self, pixels_per_unit: f32) -> Vec<f32> {
+        (0..self.lod_count)
+            .map(|lod| {
+                let vertex_spacing = self.chunk_size(lod) / CHUNK_SUBDIVISIONS as f32;
+                vertex_spacing * self.range_per_spacing(pixels_per_unit)
+            })
+            .collect()
+    }
+
+    pub fn uniform(&self, pixels_per_unit: f32, max_height: f32) -> LodUniform {
+        LodUniform {
+            subdivisions: CHUNK_SUBDIVISIONS,
+            root_chunk_size: self.chunk_size,
+            range_per_spacing: self.range_per_spacing(pixels_per_unit),
+            max_height,
+        }
+    }
+}
+
+#[expect(dead_code, reason = "unused size checks of `#[derive(ShaderType)]`")]
+mod uniform {
+    use super::*;
+
+    /// What `terrain_vertex.wgsl` needs to know to morph chunks into the next coarser LOD,
+    /// along with the terrain's bounds for `terrain.wgsl`.
+    ///
+    /// Set by `update_chunks` once the window size is known.
+    #[derive(ShaderType, Clone, Copy, PartialEq, Default)]
+    pub struct LodUniform {
+        pub subdivisions: u32,
+        pub root_chunk_size: f32,
+        /// See [`LodSettings::range_per_spacing`]
+        pub range_per_spacing: f32,
+        /// See [`crate::height_field::TerrainNoiseSettings::max_height`]
+        pub max_height: f32,
+    }
+}
+
+/// Quadtree traversal picking the chunks to render.
+pub struct LodSelection<'a> {
+    pub settings: &'a LodSettings,
+    pub cam_pos: Vec3,
+    pub ranges: Vec<f32>,
+    pub min_height: f32,
+    pub max_height: f32,
+}
+
+impl LodSelection<'_> {
+    /// Collects the chunks covering the root chunk at `pos`
+    /// as `(lod, position in chunks of that LOD)`.
+    pub fn select(&self, pos: IVec2, chunks: &mut HashSet<(u8, IVec2)>) {
+        self.select_in(self.settings.lod_count - 1, pos, chunks);
+    }
+
+    fn select_in(&self, lod: u8, pos: IVec2, chunks: &mut HashSet<(u8, IVec2)>) {
+        let size = self.settings.chunk_size(lod);
+        let min = pos.as_vec2() * size;
+        let max = min + size;
+        let closest = self.cam_pos.clamp(
+            Vec3::new(min.x, self.min_height, min.y),
+            Vec3::new(max.x, self.max_height, max.y),
+        );
+        if lod == 0 || closest.distance(self.cam_pos) >= self.ranges[lod as usize] {
+            chunks.insert((lod, pos));
+            return;
+        }
+        for child in CHILDREN {
+            self.select_in(lod - 1, pos * 2 + child, chunks);
+        }
+    }
+
+    /// Splits the selected chunks until each borders chunks at most one LOD apart, which
+    /// keeps the cracks between them shallow enough for the skirts to hide.
+    pub fn balance(&self, chunks: &mut HashSet<(u8, IVec2)>) {
+        let mut unchecked: Vec<_> = chunks.iter().copied().collect();
+        while let Some((lod, pos)) = unchecked.pop() {
+            if !chunks.contains(&(lod, pos)) {
+                // Split in the meantime
+                continue;
+            }
+            for dir in [IVec2::X, IVec2::NEG_X, IVec2::Y, IVec2::NEG_Y] {
+                // The chunk covering the neighbor, if two or more LODs coarser
+                let Some((coarse_lod, coarse_pos)) = (lod + 2..self.settings.lod_count)
+                    .map(|coarse_lod| {
+                        let scale = IVec2::splat(1 << (coarse_lod - lod));
+                        (coarse_lod, (pos + dir).div_euclid(scale))
+                    })
+                    .find(|chunk| chunks.contains(chunk))
+                else {
+                    continue;
+                };
+                chunks.remove(&(coarse_lod, coarse_pos));
+                for child in CHILDREN {
+                    let child = (coarse_lod - 1, coarse_pos * 2 + child);
+                    chunks.insert(child);
+                    unchecked.push(child);
+                }
+                // The neighbor may still be too coarse
+                unchecked.push((lod, pos));
+            }
+        }
+    }
+}
+
+/// Offsets of the four children of a chunk from twice its position.
+const CHILDREN: [IVec2; 4] = [IVec2::ZERO, IVec2::X, IVec2::Y, IVec2::ONE];
+
+/// Flat unit square with skirts around its edges.
+///
+/// Chunks scale it to their size, `terrain_vertex.wgsl` displaces it.
+pub fn chunk_mesh() -> Mesh {
+    let n = CHUNK_SUBDIVISIONS;
+    let mut positions = Vec::new();
+    let mut indices = Vec::new();
+
+    for z in 0..=n {
+        for x in 0..=n {
+            positions.push([x as f32 / n as f32 - 0.5, 0.0, z as f32 / n as f32 - 0.5]);
+        }
+    }
+    for z in 0..n {
+        for x in 0..n {
+            let i = z * (n + 1) + x;
+            indices.extend([i, i + n + 1, i + 1, i + 1, i + n + 1, i + n + 2]);
+        }
+    }
+
+    // Walk around the edge, adding a vertex below each edge vertex
+    let edge: Vec<u32> = (0..n)
+        .chain((0..n).map(|z| z * (n + 1) + n))
+        .chain((1..=n).rev().map(|x| n * (n + 1) + x))
+        .chain((1..=n).rev().map(|z| z * (n + 1)))
+        .collect();
+    let skirt_start = positions.len() as u32;
+    for &i in &edge {
+        let [x, _, z] = positions[i as usize];
+        positions.push([x, -SKIRT_DEPTH, z]);
+    }
+    for j in 0..edge.len() {
+        let next = (j + 1) % edge.len();
+        let (top, next_top) = (edge[j], edge[next]);
+        let (bottom, next_bottom) = (skirt_start + j as u32, skirt_start + next as u32);
+        // Face outwards, so the skirt is visible through the crack next to it
+        indices.extend([top, next_top, bottom, next_top, next_bottom, bottom]);
+    }
+
+    Mesh::new(
+        PrimitiveTopology::TriangleList,
+        RenderAssetUsages::RENDER_WORLD,
+    )
+    .with_inserted_attribute(Mesh::ATTRIBUTE_POSITION, positions)
+    .with_inserted_indices(Indices::U32(indices))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn balanced_chunks_tile_the_roots() {
+        let settings = LodSettings::default();
+        let root_count = 9;
+        for cam_pos in [Vec3::new(10.0, 5.0, -30.0), Vec3::new(-700.0, 300.0, 450.0)] {
+            let selection = LodSelection {
+                settings: &settings,
+                cam_pos,
+                // Finer than the screen space error would pick, for many LODs near the camera
+                ranges: (0..settings.lod_count)
+                    .map(|lod| settings.chunk_size(lod) * 1.5)
+                    .collect(),
+                min_height: -20.0,
+                max_height: 60.0,
+            };
+            let mut chunks = HashSet::new();
+            for z in -1..=1 {
+                for x in -1..=1 {
+                    selection.select(IVec2::new(x, z), &mut chunks);
+                }
+            }
+            selection.balance(&mut chunks);
+
+            // In finest chunks
+            let area: u64 = chunks.iter().map(|&(lod, _)| 1 << (2 * lod)).sum();
+            assert_eq!(area, root_count << (2 * (settings.lod_count - 1)));
+            for &(lod, pos) in &chunks {
+                for dir in [IVec2::X, IVec2::NEG_X, IVec2::Y, IVec2::NEG_Y] {
+                    for coarse_lod in lod + 2..settings.lod_count {
+                        let scale = IVec2::splat(1 << (coarse_lod - lod));
+                        let coarse = (coarse_lod, (pos + dir).div_euclid(scale));
+                        assert!(
+                            !chunks.contains(&coarse),
+                            "{:?} next to {coarse:?}",
+                            (lod, pos)
+                        );
+                    }
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn validate_rejects_unusable_settings() {
+        assert_eq!(LodSettings::default().validate(), Ok(()));
+        for settings in [
+            LodSettings {
+                render_dist: -1,
+                ..default()
+            },
+            LodSettings {
+                chunk_size: 0.0,
+                ..default()
+            },
+            LodSettings {
+                chunk_size: f32::NAN,
+                ..default()
+            },
+            LodSettings {
+                lod_count: 0,
+                ..default()
+            },
+            LodSettings {
+                lod_count: MAX_LOD_COUNT + 1,
+                ..default()
+            },
+            LodSettings {
+                max_screen_space_error: -4.0,
+                ..default()
+            },
+        ] {
+            assert!(settings.validate().is_err());
+        }
+    }
+}
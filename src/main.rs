@@ -1,72 +1,161 @@
 #![cfg_attr(bevy_lint, feature(register_tool), register_tool(bevy))]
 #![cfg_attr(not(feature = "console"), windows_subsystem = "windows")]
 
-use std::{array, borrow::Cow, f32::consts::FRAC_PI_2, mem, result::Result, time::Duration};
+mod biome;
+mod celestial;
+mod cli;
+mod clouds;
+mod environment;
+mod fullscreen;
+mod height_field;
+mod lod;
+mod pbr_terrain;
+mod seed;
+mod time_of_day;
+mod weather;
+mod world_settings;
+
+use std::{f32::consts::FRAC_PI_2, mem, result::Result};
 
 use bevy::{
-    core_pipeline::{
-        core_3d::graph::{Core3d, Node3d},
-        fullscreen_vertex_shader::fullscreen_shader_vertex_state,
+    core_pipeline::core_3d::graph::{Core3d, Node3d},
+    ecs::{
+        query::QueryItem,
+        system::{
+            SystemParamItem,
+            lifetimeless::{Read, SRes},
+        },
     },
-    ecs::{query::QueryItem, system::lifetimeless::Read},
+    image::{ImageAddressMode, ImageLoaderSettings, ImageSampler, ImageSamplerDescriptor},
     input::mouse::{AccumulatedMouseMotion, AccumulatedMouseScroll, MouseScrollUnit},
     platform::collections::HashSet,
     prelude::*,
     render::{
         Render, RenderApp, RenderSet,
         globals::{GlobalsBuffer, GlobalsUniform},
-        mesh::PlaneMeshBuilder,
         primitives::Aabb,
         render_graph::{
             NodeRunError, RenderGraphApp, RenderGraphContext, RenderLabel, ViewNode, ViewNodeRunner,
         },
         render_resource::{
-            AsBindGroup, BindGroup, BindGroupEntries, BindGroupLayout, BindGroupLayoutEntries,
-            CachedRenderPipelineId, ColorTargetState, ColorWrites, FragmentState, MultisampleState,
-            PipelineCache, RenderPassColorAttachment, RenderPassDescriptor,
-            RenderPipelineDescriptor, Sampler, SamplerBindingType, ShaderRef, ShaderStages,
-            SpecializedRenderPipeline, SpecializedRenderPipelines, TextureFormat,
-            TextureSampleType, TextureUsages,
-            binding_types::{sampler, texture_2d, texture_2d_multisampled, uniform_buffer},
+            AsBindGroup, AsBindGroupError, BindGroup, BindGroupEntries, BindGroupLayout,
+            BindGroupLayoutEntries, BindGroupLayoutEntry, CachedRenderPipelineId, MultisampleState,
+            OwnedBindingResource, PipelineCache, RenderPassDescriptor, RenderPipelineDescriptor,
+            ShaderRef, ShaderStages, ShaderType, SpecializedRenderPipeline,
+            SpecializedRenderPipelines, TextureUsages, UnpreparedBindGroup,
+            binding_types::uniform_buffer,
         },
         renderer::{RenderContext, RenderDevice},
-        view::{ViewDepthTexture, ViewTarget, ViewUniform, ViewUniformOffset, ViewUniforms},
+        view::{ViewTarget, ViewUniform, ViewUniformOffset, ViewUniforms},
     },
-    time::common_conditions::on_timer,
     window::WindowMode,
 };
+use clap::Parser;
 use noisy_bevy::NoisyShaderPlugin;
 
+use celestial::Observer;
+use cli::Cli;
+use clouds::CloudsPlugin;
+use environment::{
+    CloudsEnabled, ENVIRONMENT_BINDING, EnvironmentBuffer, EnvironmentPlugin, EnvironmentUniform,
+};
+use fullscreen::{PostProcess, add_post_process, fullscreen_pipeline};
+use height_field::{TerrainHeightField, TerrainNoiseSettings};
+use lod::{LodSelection, LodSettings, LodUniform, SKIRT_DEPTH, chunk_mesh};
+use pbr_terrain::{PbrTerrainMaterialHandle, PbrTerrainPlugin};
+use seed::{WorldSeed, apply_world_seed, hash};
+use serde::Deserialize;
+use shading::TerrainShading;
+use time_of_day::{TimeOfDay, TimeOfDayPlugin, time_of_day_slider};
+use weather::{Weather, WeatherPlugin};
+use world_settings::WorldSettingsPlugin;
+
 fn main() -> AppExit {
-    App::new()
-        .add_plugins((
-            DefaultPlugins,
-            NoisyShaderPlugin,
-            MaterialPlugin::<TerrainMaterial>::default(),
-            #[cfg(feature = "frame_time_diagnostics")]
-            (
-                bevy::diagnostic::LogDiagnosticsPlugin::default(),
-                bevy::diagnostic::FrameTimeDiagnosticsPlugin::default(),
-            ),
-            SkyPlugin,
-            WaterPlugin,
-        ))
-        .init_state::<AppState>()
-        .add_systems(Startup, (setup, update_chunks).chain())
-        .add_systems(
-            Update,
+    let cli = Cli::parse();
+
+    let mut app = App::new();
+    app.add_plugins((
+        DefaultPlugins.set(WindowPlugin {
+            primary_window: Some(Window {
+                mode: cli.window_mode.into(),
+                ..default()
+            }),
+            ..default()
+        }),
+        NoisyShaderPlugin,
+        MaterialPlugin::<TerrainMaterial>::default(),
+        #[cfg(feature = "frame_time_diagnostics")]
+        (
+            bevy::diagnostic::LogDiagnosticsPlugin::default(),
+            bevy::diagnostic::FrameTimeDiagnosticsPlugin::default(),
+        ),
+        EnvironmentPlugin,
+        TimeOfDayPlugin,
+        WeatherPlugin,
+        WorldSettingsPlugin,
+    ));
+    if !cli.no_sky {
+        app.add_plugins(SkyPlugin {
+            milky_way: !cli.no_milky_way,
+        });
+    }
+    if !cli.no_water {
+        app.add_plugins(WaterPlugin);
+    }
+    if !cli.no_clouds {
+        app.add_plugins(CloudsPlugin);
+    }
+    if cli.pbr {
+        app.add_plugins(PbrTerrainPlugin);
+    }
+    if let Some(seed) = cli.seed {
+        app.insert_resource(WorldSeed(seed));
+    }
+    if let Some(kind) = cli.weather {
+        app.insert_resource(Weather::new(kind));
+    }
+    app.insert_resource(LodSettings {
+        render_dist: cli
+            .render_dist
+            .unwrap_or(LodSettings::default().render_dist),
+        ..default()
+    })
+    .insert_resource(Observer {
+        latitude: cli.latitude.unwrap_or(Observer::default().latitude),
+    })
+    .insert_resource(CloudsEnabled(!cli.no_clouds))
+    .insert_resource(cli.time_of_day())
+    .insert_resource(cli)
+    .init_state::<AppState>()
+    .init_resource::<TerrainHeightField>()
+    .init_resource::<WalkSettings>()
+    .init_resource::<FlyCollision>()
+    .init_resource::<MouseSensitivity>()
+    .add_systems(Startup, (apply_world_seed, setup, update_chunks).chain())
+    .add_systems(
+        Update,
+        (
             (
-                (
-                    update_chunks.run_if(on_timer(Duration::from_secs(1))),
-                    move_cam,
-                )
-                    .run_if(in_state(AppState::Running)),
-                update_state,
-                toggle_fullscreen,
-            ),
-        )
-        .add_systems(OnEnter(AppState::Paused), on_pause)
-        .run()
+                // After the camera moved, to pick the chunks around where it is
+                (toggle_walk, move_cam, walk, update_chunks).chain(),
+                toggle_fly_collision,
+            )
+                .run_if(in_state(AppState::Running)),
+            update_state,
+            (highlight_buttons, toggle_fullscreen, new_seed),
+            update_seed_text.run_if(resource_changed::<WorldSeed>),
+            update_biome_text
+                .run_if(resource_changed::<TerrainHeightField>)
+                .after(apply_world_seed),
+            apply_world_seed.run_if(resource_changed::<WorldSeed>),
+            update_terrain_noise
+                .run_if(resource_changed::<TerrainHeightField>)
+                .after(apply_world_seed),
+            update_terrain_shading.run_if(resource_changed::<TerrainShading>),
+        ),
+    )
+    .add_systems(OnEnter(AppState::Paused), on_pause);
+    app.run()
 }
 
 #[derive(States, Debug, PartialEq, Eq, Hash, Clone, Default)]
@@ -77,8 +166,187 @@ enum AppState {
     Paused,
 }
 
-#[derive(AsBindGroup, Clone, Asset, TypePath)]
-struct TerrainMaterial {}
+/// Also extends `StandardMaterial` with `--pbr`, hence the bindings clear of its own.
+///
+/// Binds the [`EnvironmentBuffer`] of the fullscreen passes at [`TERRAIN_ENVIRONMENT_BINDING`]
+/// next to [`TerrainBindings`], rather than a copy of it that would change every frame.
+#[derive(Clone, Asset, TypePath, Default, Deref, DerefMut)]
+struct TerrainMaterial(TerrainBindings);
+
+const TERRAIN_ENVIRONMENT_BINDING: u32 = 103;
+
+#[derive(AsBindGroup, Clone, Default)]
+struct TerrainBindings {
+    #[uniform(100)]
+    noise: TerrainNoiseSettings,
+    #[uniform(101)]
+    shading: TerrainShading,
+    #[uniform(102)]
+    lod: LodUniform,
+    /// Texture arrays with a layer for each of grass, rock, dirt, sand and snow,
+    /// see `terrain_layers.wgsl`
+    #[texture(104, dimension = "2d_array")]
+    #[sampler(105)]
+    albedo_textures: Handle<Image>,
+    #[texture(106, dimension = "2d_array")]
+    normal_textures: Handle<Image>,
+    #[texture(107, dimension = "2d_array")]
+    roughness_textures: Handle<Image>,
+}
+
+#[expect(dead_code, reason = "unused size checks of `#[derive(ShaderType)]`")]
+mod shading {
+    use super::*;
+
+    /// Passed on to the terrain material, and to the water through
+    /// [`crate::environment::EnvironmentUniform`].
+    #[derive(Resource, ShaderType, Deserialize, Clone, Copy, PartialEq)]
+    pub struct TerrainShading {
+        /// Exaggerates the haze of the atmosphere on distant terrain, as if it was that many times farther away
+        aerial_perspective_scale: f32,
+        /// Of each biome, see `Biome`
+        pub grass_color: LinearRgba,
+        desert_color: LinearRgba,
+        tundra_color: LinearRgba,
+        forest_color: LinearRgba,
+        swamp_color: LinearRgba,
+        alpine_color: LinearRgba,
+        beach_color: LinearRgba,
+        /// Of steep faces
+        rock_color: LinearRgba,
+        /// Of moderate slopes, between the biome and the rock
+        dirt_color: LinearRgba,
+        snow_color: LinearRgba,
+        /// Slope at which the terrain is fully covered in rock
+        slope_threshold: f32,
+        /// Altitude above which the terrain is covered in snow
+        snow_line: f32,
+        /// Altitude range over which the snow fades out around the snow line
+        snow_blend: f32,
+        /// Slope above which snow doesn't settle
+        snow_max_slope: f32,
+        /// How much higher the snow line is on slopes facing the sun's path, per unit of slope
+        snow_sun_melt: f32,
+        /// Width of the terrain covered by each tile of the layer textures up close
+        texture_size: f32,
+        /// How many times wider the tiles are in the distance, hiding the repetition
+        far_texture_scale: f32,
+        /// Distance from the camera at which the textures are fully switched to the far tiles
+        detail_distance: f32,
+    }
+
+    impl Default for TerrainShading {
+        fn default() -> Self {
+            Self {
+                aerial_perspective_scale: 4.0,
+                grass_color: LinearRgba::rgb(0.1, 0.4, 0.0),
+                desert_color: LinearRgba::rgb(0.6, 0.45, 0.25),
+                tundra_color: LinearRgba::rgb(0.35, 0.35, 0.3),
+                forest_color: LinearRgba::rgb(0.03, 0.2, 0.02),
+                swamp_color: LinearRgba::rgb(0.1, 0.15, 0.05),
+                alpine_color: LinearRgba::rgb(0.3, 0.3, 0.25),
+                beach_color: LinearRgba::rgb(0.7, 0.6, 0.4),
+                rock_color: LinearRgba::rgb(0.2, 0.2, 0.1),
+                dirt_color: LinearRgba::rgb(0.25, 0.17, 0.08),
+                snow_color: LinearRgba::rgb(0.9, 0.9, 0.95),
+                slope_threshold: 2.0,
+                snow_line: 65.0,
+                snow_blend: 8.0,
+                snow_max_slope: 1.2,
+                snow_sun_melt: 20.0,
+                texture_size: 4.0,
+                far_texture_scale: 8.0,
+                detail_distance: 80.0,
+            }
+        }
+    }
+
+    impl TerrainShading {
+        /// Checks the settings loaded from the world settings, which would leave holes in the
+        /// terrain if they weren't numbers.
+        pub fn validate(&self) -> Result<(), String> {
+            let colors = [
+                ("grass_color", self.grass_color),
+                ("desert_color", self.desert_color),
+                ("tundra_color", self.tundra_color),
+                ("forest_color", self.forest_color),
+                ("swamp_color", self.swamp_color),
+                ("alpine_color", self.alpine_color),
+                ("beach_color", self.beach_color),
+                ("rock_color", self.rock_color),
+                ("dirt_color", self.dirt_color),
+                ("snow_color", self.snow_color),
+            ];
+            for (name, color) in colors {
+                if !color.to_f32_array().iter().all(|c| c.is_finite()) {
+                    return Err(format!("shading {name} isn't finite: {color:?}"));
+                }
+            }
+            for (name, value) in [
+                ("aerial_perspective_scale", self.aerial_perspective_scale),
+                ("slope_threshold", self.slope_threshold),
+                ("snow_line", self.snow_line),
+                ("snow_blend", self.snow_blend),
+                ("snow_max_slope", self.snow_max_slope),
+                ("snow_sun_melt", self.snow_sun_melt),
+                ("texture_size", self.texture_size),
+                ("far_texture_scale", self.far_texture_scale),
+                ("detail_distance", self.detail_distance),
+            ] {
+                if !value.is_finite() {
+                    return Err(format!("shading {name} isn't a finite number: {value}"));
+                }
+            }
+            Ok(())
+        }
+    }
+}
+
+impl AsBindGroup for TerrainMaterial {
+    type Data = <TerrainBindings as AsBindGroup>::Data;
+    type Param = (
+        <TerrainBindings as AsBindGroup>::Param,
+        SRes<EnvironmentBuffer>,
+    );
+
+    fn label() -> Option<&'static str> {
+        Some("terrain_material")
+    }
+
+    fn unprepared_bind_group(
+        &self,
+        layout: &BindGroupLayout,
+        render_device: &RenderDevice,
+        (param, environment_buffer): &mut SystemParamItem<'_, '_, Self::Param>,
+        force_no_bindless: bool,
+    ) -> Result<UnpreparedBindGroup<Self::Data>, AsBindGroupError> {
+        // Only written once the first frame is prepared
+        let Some(environment) = environment_buffer.0.buffer() else {
+            return Err(AsBindGroupError::RetryNextUpdate);
+        };
+        let mut bind_group =
+            self.0
+                .unprepared_bind_group(layout, render_device, param, force_no_bindless)?;
+        bind_group.bindings.push((
+            TERRAIN_ENVIRONMENT_BINDING,
+            OwnedBindingResource::Buffer(environment.clone()),
+        ));
+        Ok(bind_group)
+    }
+
+    fn bind_group_layout_entries(
+        render_device: &RenderDevice,
+        force_no_bindless: bool,
+    ) -> Vec<BindGroupLayoutEntry> {
+        let mut entries =
+            TerrainBindings::bind_group_layout_entries(render_device, force_no_bindless);
+        entries.push(
+            uniform_buffer::<EnvironmentUniform>(false)
+                .build(TERRAIN_ENVIRONMENT_BINDING, ShaderStages::VERTEX_FRAGMENT),
+        );
+        entries
+    }
+}
 
 impl Material for TerrainMaterial {
     fn vertex_shader() -> ShaderRef {
@@ -90,72 +358,83 @@ impl Material for TerrainMaterial {
     }
 }
 
-/// (start_height - (1 - gain ^ (octaves + 1)) / (1 - gain)) ^ 2 * amp
-const TERRAIN_MIN_HEIGHT: f32 = -22.470703;
-/// (start_height + (1 - gain ^ (octaves + 1)) / (1 - gain)) ^ 2 * amp
-const TERRAIN_MAX_HEIGHT: f32 = 93.60358;
-
-const TERRAIN_CENTER_HEIGHT: f32 = (TERRAIN_MIN_HEIGHT + TERRAIN_MAX_HEIGHT) / 2.0;
-const TERRAIN_HALF_HEIGHT: f32 = TERRAIN_MAX_HEIGHT - TERRAIN_CENTER_HEIGHT;
-
-const LOD_COUNT: u8 = 6;
-
 #[derive(Component)]
-struct Chunk;
+struct Chunk {
+    lod: u8,
+    /// Position in chunks of the same LOD
+    pos: IVec2,
+}
 
 #[derive(Resource)]
-struct ChunkMeshes([Handle<Mesh>; LOD_COUNT as usize]);
-
-impl ChunkMeshes {
-    fn get(&self, dist_squared: f32) -> Handle<Mesh> {
-        let i = if dist_squared < 40000.0 {
-            0
-        } else if dist_squared < 160000.0 {
-            1
-        } else if dist_squared < 640000.0 {
-            2
-        } else if dist_squared < 2560000.0 {
-            3
-        } else if dist_squared < 10240000.0 {
-            4
-        } else {
-            5
-        };
-        self.0[i].clone()
-    }
-}
+struct ChunkMesh(Handle<Mesh>);
 
 #[derive(Resource)]
 struct TerrainMaterialHandle(Handle<TerrainMaterial>);
 
-const CHUNK_SIZE: f32 = 200.0;
-
 fn setup(
     mut commands: Commands,
     asset_server: Res<AssetServer>,
     mut meshes: ResMut<Assets<Mesh>>,
     mut materials: ResMut<Assets<TerrainMaterial>>,
+    height_field: Res<TerrainHeightField>,
+    cli: Res<Cli>,
 ) {
-    commands.spawn(Camera3d {
-        depth_texture_usages: (TextureUsages::RENDER_ATTACHMENT | TextureUsages::TEXTURE_BINDING)
-            .into(),
-        ..default()
-    });
+    commands.spawn((
+        Camera3d {
+            depth_texture_usages: (TextureUsages::RENDER_ATTACHMENT
+                | TextureUsages::TEXTURE_BINDING)
+                .into(),
+            ..default()
+        },
+        cli.cam_transform(height_field.height_at(Vec2::ZERO)),
+    ));
     mem::forget(asset_server.load::<Shader>("shaders/common.wgsl"));
+    mem::forget(asset_server.load::<Shader>("shaders/terrain_biomes.wgsl"));
+    mem::forget(asset_server.load::<Shader>("shaders/terrain_layers.wgsl"));
+    mem::forget(asset_server.load::<Shader>("shaders/terrain_noise.wgsl"));
+    mem::forget(asset_server.load::<Shader>("shaders/terrain_vertex.wgsl"));
 
-    commands.insert_resource(ChunkMeshes(array::from_fn(|i| {
-        meshes.add(
-            PlaneMeshBuilder {
-                plane: Plane3d {
-                    half_size: Vec2::splat(CHUNK_SIZE / 2.0),
-                    ..default()
-                },
-                subdivisions: 1024 / 2u32.pow(i as u32),
-            }
-            .build(),
-        )
-    })));
-    commands.insert_resource(TerrainMaterialHandle(materials.add(TerrainMaterial {})));
+    let load_texture = |path: &'static str, is_srgb: bool| {
+        asset_server.load_with_settings(path, move |settings: &mut ImageLoaderSettings| {
+            settings.is_srgb = is_srgb;
+            settings.sampler = ImageSampler::Descriptor(ImageSamplerDescriptor {
+                address_mode_u: ImageAddressMode::Repeat,
+                address_mode_v: ImageAddressMode::Repeat,
+                anisotropy_clamp: 16,
+                ..ImageSamplerDescriptor::linear()
+            });
+        })
+    };
+    commands.insert_resource(ChunkMesh(meshes.add(chunk_mesh())));
+    commands.insert_resource(TerrainMaterialHandle(materials.add(TerrainMaterial(
+        TerrainBindings {
+            noise: height_field.noise,
+            albedo_textures: load_texture("textures/terrain_albedo.ktx2", true),
+            normal_textures: load_texture("textures/terrain_normal.ktx2", false),
+            roughness_textures: load_texture("textures/terrain_roughness.ktx2", false),
+            ..default()
+        },
+    ))));
+}
+
+fn update_terrain_shading(
+    shading: Res<TerrainShading>,
+    material: Res<TerrainMaterialHandle>,
+    mut materials: ResMut<Assets<TerrainMaterial>>,
+) {
+    if let Some(material) = materials.get_mut(&material.0) {
+        material.shading = *shading;
+    }
+}
+
+fn update_terrain_noise(
+    height_field: Res<TerrainHeightField>,
+    material: Res<TerrainMaterialHandle>,
+    mut materials: ResMut<Assets<TerrainMaterial>>,
+) {
+    if let Some(material) = materials.get_mut(&material.0) {
+        material.noise = height_field.noise;
+    }
 }
 
 fn update_state(
@@ -171,7 +450,39 @@ fn update_state(
     }
 }
 
-fn on_pause(mut commands: Commands) {
+#[derive(Component)]
+struct FullscreenButton;
+
+#[derive(Component)]
+struct NewSeedButton;
+
+#[derive(Component)]
+struct SeedText;
+
+#[derive(Component)]
+struct BiomeText;
+
+fn on_pause(
+    mut commands: Commands,
+    seed: Res<WorldSeed>,
+    time_of_day: Res<TimeOfDay>,
+    height_field: Res<TerrainHeightField>,
+    cam: Single<&Transform, With<Camera>>,
+) {
+    let button = || {
+        (
+            Button,
+            Node {
+                height: Val::Px(50.0),
+                width: Val::Percent(70.0),
+                justify_content: JustifyContent::Center,
+                align_items: AlignItems::Center,
+                ..default()
+            },
+            BorderRadius::all(Val::Percent(100.0)),
+        )
+    };
+
     commands.spawn((
         StateScoped(AppState::Paused),
         Node {
@@ -190,102 +501,210 @@ fn on_pause(mut commands: Commands) {
         children![
             (Text::new("Paused"), TextFont::from_font_size(50.0)),
             (
-                Button,
-                Node {
-                    height: Val::Px(50.0),
-                    width: Val::Percent(70.0),
-                    justify_content: JustifyContent::Center,
-                    align_items: AlignItems::Center,
-                    ..default()
-                },
-                BorderRadius::all(Val::Percent(100.0)),
+                button(),
+                FullscreenButton,
                 children![(
                     Text::new("Toggle fullscreen"),
                     TextFont::from_font_size(30.0),
                 )]
-            )
+            ),
+            (
+                Text::new(seed_text(*seed)),
+                TextFont::from_font_size(30.0),
+                SeedText,
+            ),
+            (
+                Text::new(biome_text(&height_field, &cam)),
+                TextFont::from_font_size(30.0),
+                BiomeText,
+            ),
+            (
+                button(),
+                NewSeedButton,
+                children![(Text::new("New seed"), TextFont::from_font_size(30.0))]
+            ),
+            time_of_day_slider(&time_of_day),
         ],
     ));
 }
 
-fn toggle_fullscreen(
+fn highlight_buttons(
     mut q: Query<(&Interaction, &mut BackgroundColor), (Changed<Interaction>, With<Button>)>,
-    mut window: Single<&mut Window>,
 ) {
     for (interaction, mut bg) in &mut q {
         bg.0 = match *interaction {
-            Interaction::Pressed => {
-                window.mode = match window.mode {
-                    WindowMode::Windowed => {
-                        WindowMode::BorderlessFullscreen(MonitorSelection::Current)
-                    }
-                    _ => WindowMode::Windowed,
-                };
-                Color::srgb(0.4, 0.4, 0.4)
-            }
+            Interaction::Pressed => Color::srgb(0.4, 0.4, 0.4),
             Interaction::Hovered => Color::srgb(0.2, 0.2, 0.2),
             Interaction::None => Color::BLACK,
         };
     }
 }
 
-const RENDER_DIST: i32 = 32;
+fn toggle_fullscreen(
+    q: Query<&Interaction, (Changed<Interaction>, With<FullscreenButton>)>,
+    mut window: Single<&mut Window>,
+) {
+    for interaction in &q {
+        if *interaction == Interaction::Pressed {
+            window.mode = match window.mode {
+                WindowMode::Windowed => WindowMode::BorderlessFullscreen(MonitorSelection::Current),
+                _ => WindowMode::Windowed,
+            };
+        }
+    }
+}
+
+fn new_seed(
+    q: Query<&Interaction, (Changed<Interaction>, With<NewSeedButton>)>,
+    mut seed: ResMut<WorldSeed>,
+    time: Res<Time<Real>>,
+) {
+    for interaction in &q {
+        if *interaction == Interaction::Pressed {
+            // The time of the click is as good as random
+            seed.0 = hash(seed.0 ^ time.elapsed().as_nanos() as u32);
+        }
+    }
+}
+
+fn seed_text(seed: WorldSeed) -> String {
+    format!("Seed: {}", seed.0)
+}
+
+fn update_seed_text(seed: Res<WorldSeed>, mut text: Query<&mut Text, With<SeedText>>) {
+    for mut text in &mut text {
+        text.0 = seed_text(*seed);
+    }
+}
+
+/// Names the biome under the camera.
+fn biome_text(height_field: &TerrainHeightField, cam: &Transform) -> String {
+    format!("Biome: {:?}", height_field.biome_at(cam.translation.xz()))
+}
+
+fn update_biome_text(
+    height_field: Res<TerrainHeightField>,
+    cam: Single<&Transform, With<Camera>>,
+    mut text: Query<&mut Text, With<BiomeText>>,
+) {
+    for mut text in &mut text {
+        text.0 = biome_text(&height_field, &cam);
+    }
+}
 
 fn update_chunks(
-    chunk_q: Query<(&Transform, Entity), With<Chunk>>,
+    chunk_q: Query<(&Chunk, Entity)>,
     mut commands: Commands,
-    meshes: Res<ChunkMeshes>,
+    mesh: Res<ChunkMesh>,
     material: Res<TerrainMaterialHandle>,
-    cam: Single<&Transform, With<Camera>>,
+    pbr_material: Option<Res<PbrTerrainMaterialHandle>>,
+    mut materials: ResMut<Assets<TerrainMaterial>>,
+    cam: Single<(&Transform, &Projection), With<Camera>>,
+    window: Single<&Window>,
+    height_field: Res<TerrainHeightField>,
+    lod_settings: Res<LodSettings>,
+    mut selected_at: Local<Option<(IVec3, f32)>>,
 ) {
-    let mut chunks = HashSet::new();
-    for (tf, e) in chunk_q.iter() {
-        let dist_squared = tf.translation.xz().distance_squared(cam.translation.xz());
-        if dist_squared > (RENDER_DIST * RENDER_DIST) as f32 * CHUNK_SIZE * CHUNK_SIZE {
-            commands.entity(e).despawn();
-            continue;
-        }
-        chunks.insert((tf.translation.xz() / CHUNK_SIZE).as_ivec2());
-        commands.entity(e).insert(Mesh3d(meshes.get(dist_squared)));
+    let (cam_tf, projection) = *cam;
+    let Projection::Perspective(projection) = projection else {
+        return;
+    };
+    let pixels_per_unit = window.physical_height() as f32 / (2.0 * (projection.fov / 2.0).tan());
+
+    let lod = lod_settings.uniform(pixels_per_unit, height_field.noise.max_height());
+    // Avoid marking the material as changed every frame
+    if materials.get(&material.0).is_some_and(|m| m.lod != lod)
+        && let Some(m) = materials.get_mut(&material.0)
+    {
+        m.lod = lod;
     }
-    for z in -RENDER_DIST..RENDER_DIST {
-        for x in -RENDER_DIST..RENDER_DIST {
-            let pos = Vec2::new(x as f32, z as f32);
-            if pos.length_squared() > (RENDER_DIST * RENDER_DIST) as f32 {
-                continue;
-            }
-            let pos = pos + (cam.translation.xz() / CHUNK_SIZE).round();
-            if chunks.contains(&pos.as_ivec2()) {
+
+    // Within a finest chunk the selection barely changes, so only redo it across their boundaries
+    let cam_cell = (cam_tf.translation / lod_settings.chunk_size(0))
+        .floor()
+        .as_ivec3();
+    if !lod_settings.is_changed()
+        && !height_field.is_changed()
+        && *selected_at == Some((cam_cell, pixels_per_unit))
+    {
+        return;
+    }
+    *selected_at = Some((cam_cell, pixels_per_unit));
+
+    let selection = LodSelection {
+        settings: &lod_settings,
+        cam_pos: cam_tf.translation,
+        ranges: lod_settings.ranges(pixels_per_unit),
+        min_height: height_field.noise.min_height(),
+        max_height: height_field.noise.max_height(),
+    };
+
+    let mut chunks = HashSet::new();
+    let render_dist = lod_settings.render_dist;
+    let cam_chunk = (cam_tf.translation.xz() / lod_settings.chunk_size)
+        .floor()
+        .as_ivec2();
+    for z in -render_dist..=render_dist {
+        for x in -render_dist..=render_dist {
+            if x * x + z * z > render_dist * render_dist {
                 continue;
             }
-            let pos = pos * CHUNK_SIZE;
-            commands.spawn((
-                Chunk,
-                Mesh3d(meshes.get(pos.distance_squared(cam.translation.xz()))),
-                MeshMaterial3d(material.0.clone()),
-                Transform::from_xyz(pos.x, 0.0, pos.y),
-                Aabb {
-                    center: Vec3A::new(0.0, TERRAIN_CENTER_HEIGHT, 0.0),
-                    half_extents: Vec3A::new(
-                        CHUNK_SIZE / 2.0,
-                        TERRAIN_HALF_HEIGHT,
-                        CHUNK_SIZE / 2.0,
-                    ),
-                },
-            ));
+            selection.select(cam_chunk + IVec2::new(x, z), &mut chunks);
         }
     }
+    selection.balance(&mut chunks);
+
+    for (chunk, e) in &chunk_q {
+        // Chunk positions are meaningless once chunk sizes change
+        if lod_settings.is_changed() || !chunks.remove(&(chunk.lod, chunk.pos)) {
+            commands.entity(e).despawn();
+        } else if height_field.is_changed() {
+            let size = lod_settings.chunk_size(chunk.lod);
+            commands
+                .entity(e)
+                .insert(chunk_aabb(&height_field.noise, size));
+        }
+    }
+    for (lod, pos) in chunks {
+        let size = lod_settings.chunk_size(lod);
+        let center = (pos.as_vec2() + 0.5) * size;
+        let mut chunk = commands.spawn((
+            Chunk { lod, pos },
+            Mesh3d(mesh.0.clone()),
+            Transform::from_xyz(center.x, 0.0, center.y).with_scale(Vec3::splat(size)),
+            chunk_aabb(&height_field.noise, size),
+        ));
+        match &pbr_material {
+            Some(pbr_material) => chunk.insert(MeshMaterial3d(pbr_material.0.clone())),
+            None => chunk.insert(MeshMaterial3d(material.0.clone())),
+        };
+    }
+}
+
+/// The mesh is flat, so its bounds have to account for the displacement in `terrain_vertex.wgsl`.
+/// In local space, i.e. relative to the chunk's size.
+fn chunk_aabb(noise: &TerrainNoiseSettings, size: f32) -> Aabb {
+    let min = noise.min_height() / size - SKIRT_DEPTH;
+    let max = noise.max_height() / size;
+    Aabb {
+        center: Vec3A::new(0.0, (min + max) / 2.0, 0.0),
+        half_extents: Vec3A::new(0.5, (max - min) / 2.0, 0.5),
+    }
 }
 
 fn move_cam(
     accumulated_mouse_motion: Res<AccumulatedMouseMotion>,
     scroll: Res<AccumulatedMouseScroll>,
     kb: Res<ButtonInput<KeyCode>>,
-    mut tf: Single<&mut Transform, With<Camera>>,
+    cam: Single<(&mut Transform, Has<Walking>), With<Camera>>,
     mut speed: Local<f32>,
     time: Res<Time>,
+    height_field: Res<TerrainHeightField>,
+    collision: Res<FlyCollision>,
+    sensitivity: Res<MouseSensitivity>,
 ) {
-    let sensi = Vec2::new(0.003, 0.002);
+    let (mut tf, walking) = cam.into_inner();
+    let sensi = sensitivity.0;
 
     let delta = accumulated_mouse_motion.delta;
 
@@ -314,6 +733,11 @@ fn move_cam(
         tf.rotation = Quat::from_euler(EulerRot::YXZ, yaw, pitch, roll);
     }
 
+    if walking {
+        // Movement is handled by `walk`
+        return;
+    }
+
     if *speed == 0.0 {
         *speed = 50.0;
     }
@@ -345,18 +769,174 @@ fn move_cam(
     }
 
     let rot = tf.rotation;
-    tf.translation += rot * dir.normalize_or_zero() * *speed * time.delta_secs();
+    let mut delta = rot * dir.normalize_or_zero() * *speed * time.delta_secs();
+
+    if collision.enabled {
+        let target = tf.translation + delta;
+        if target.y < height_field.height_at(target.xz()) + collision.clearance {
+            // Slide along the surface instead of stopping
+            let normal = height_field.normal_at(target.xz());
+            delta -= normal * delta.dot(normal).min(0.0);
+        }
+    }
+    tf.translation += delta;
+
+    if collision.enabled {
+        let min_height = height_field.height_at(tf.translation.xz()) + collision.clearance;
+        tf.translation.y = tf.translation.y.max(min_height);
+    }
+}
+
+#[derive(Resource)]
+struct MouseSensitivity(Vec2);
+
+impl Default for MouseSensitivity {
+    fn default() -> Self {
+        Self(Vec2::new(0.003, 0.002))
+    }
+}
+
+/// Keeps the flying camera above the terrain.
+#[derive(Resource, Deserialize, Clone)]
+struct FlyCollision {
+    enabled: bool,
+    clearance: f32,
+}
+
+impl Default for FlyCollision {
+    fn default() -> Self {
+        Self {
+            enabled: true,
+            clearance: 1.0,
+        }
+    }
+}
+
+fn toggle_fly_collision(kb: Res<ButtonInput<KeyCode>>, mut collision: ResMut<FlyCollision>) {
+    if kb.just_pressed(KeyCode::KeyC) {
+        collision.enabled = !collision.enabled;
+    }
+}
+
+/// Keeps the camera on the ground instead of flying freely.
+#[derive(Component, Default)]
+struct Walking {
+    vertical_velocity: f32,
+    grounded: bool,
+}
+
+#[derive(Resource, Deserialize, Clone)]
+struct WalkSettings {
+    eye_height: f32,
+    speed: f32,
+    gravity: f32,
+    jump_speed: f32,
+    /// Steepest slope (in radians) that can be walked up
+    max_slope: f32,
+}
+
+impl Default for WalkSettings {
+    fn default() -> Self {
+        Self {
+            eye_height: 1.7,
+            speed: 5.0,
+            gravity: 9.81,
+            jump_speed: 5.0,
+            max_slope: 40f32.to_radians(),
+        }
+    }
 }
 
-struct SkyPlugin;
+fn toggle_walk(
+    mut commands: Commands,
+    kb: Res<ButtonInput<KeyCode>>,
+    cam: Single<(Entity, Has<Walking>), With<Camera>>,
+) {
+    if !kb.just_pressed(KeyCode::KeyF) {
+        return;
+    }
+    let (e, walking) = *cam;
+    if walking {
+        commands.entity(e).remove::<Walking>();
+    } else {
+        commands.entity(e).insert(Walking::default());
+    }
+}
+
+fn walk(
+    kb: Res<ButtonInput<KeyCode>>,
+    cam: Single<(&mut Transform, &mut Walking), With<Camera>>,
+    height_field: Res<TerrainHeightField>,
+    settings: Res<WalkSettings>,
+    time: Res<Time>,
+) {
+    let (mut tf, mut walking) = cam.into_inner();
+
+    let forward = tf.forward().xz().normalize_or_zero();
+    let right = tf.right().xz().normalize_or_zero();
+    let mut dir = Vec2::ZERO;
+
+    if kb.pressed(KeyCode::KeyW) {
+        dir += forward;
+    }
+    if kb.pressed(KeyCode::KeyS) {
+        dir -= forward;
+    }
+    if kb.pressed(KeyCode::KeyA) {
+        dir -= right;
+    }
+    if kb.pressed(KeyCode::KeyD) {
+        dir += right;
+    }
+    let dir = dir.normalize_or_zero();
+
+    let step = dir * settings.speed * time.delta_secs();
+    let target = tf.translation.xz() + step;
+    // Only block walking uphill, so we can't get stuck on a steep slope
+    let uphill_slope = height_field.slope_at(target).dot(dir);
+    if !walking.grounded || uphill_slope <= settings.max_slope.tan() {
+        tf.translation.x = target.x;
+        tf.translation.z = target.y;
+    }
+
+    let ground = height_field.height_at(tf.translation.xz()) + settings.eye_height;
+
+    if walking.grounded && kb.just_pressed(KeyCode::Space) {
+        walking.vertical_velocity = settings.jump_speed;
+        walking.grounded = false;
+    }
+
+    if walking.grounded {
+        // Stick to the ground when walking downhill, unless we walked off a cliff
+        let max_drop = step.length() * settings.max_slope.tan();
+        if tf.translation.y - ground <= max_drop {
+            tf.translation.y = ground;
+            return;
+        }
+        walking.grounded = false;
+    }
+
+    walking.vertical_velocity -= settings.gravity * time.delta_secs();
+    tf.translation.y += walking.vertical_velocity * time.delta_secs();
+    if tf.translation.y <= ground {
+        tf.translation.y = ground;
+        walking.vertical_velocity = 0.0;
+        walking.grounded = true;
+    }
+}
+
+struct SkyPlugin {
+    milky_way: bool,
+}
 
 impl Plugin for SkyPlugin {
     fn build(&self, _app: &mut App) {}
 
     fn finish(&self, app: &mut App) {
-        app.get_sub_app_mut(RenderApp)
-            .expect("No RenderApp")
-            .init_resource::<SkyPipelineSpecializer>()
+        let render_app = app.get_sub_app_mut(RenderApp).expect("No RenderApp");
+        let specializer = SkyPipelineSpecializer::new(render_app.world(), self.milky_way);
+        render_app
+            .insert_resource(specializer)
             .init_resource::<SpecializedRenderPipelines<SkyPipelineSpecializer>>()
             .add_systems(
                 Render,
@@ -374,10 +954,11 @@ impl Plugin for SkyPlugin {
 struct SkyPipelineSpecializer {
     shader: Handle<Shader>,
     layout: BindGroupLayout,
+    milky_way: bool,
 }
 
-impl FromWorld for SkyPipelineSpecializer {
-    fn from_world(world: &mut World) -> Self {
+impl SkyPipelineSpecializer {
+    fn new(world: &World, milky_way: bool) -> Self {
         let rd = world.resource::<RenderDevice>();
         Self {
             shader: world.load_asset("shaders/sky.wgsl"),
@@ -393,9 +974,14 @@ impl FromWorld for SkyPipelineSpecializer {
                     (
                         (3, uniform_buffer::<ViewUniform>(true)),
                         (11, uniform_buffer::<GlobalsUniform>(false)),
+                        (
+                            ENVIRONMENT_BINDING,
+                            uniform_buffer::<EnvironmentUniform>(false),
+                        ),
                     ),
                 ),
             ),
+            milky_way,
         }
     }
 }
@@ -404,29 +990,19 @@ impl SpecializedRenderPipeline for SkyPipelineSpecializer {
     type Key = PipelineKey;
 
     fn specialize(&self, key: Self::Key) -> RenderPipelineDescriptor {
-        RenderPipelineDescriptor {
-            label: None,
-            layout: vec![self.layout.clone()],
-            push_constant_ranges: vec![],
-            vertex: fullscreen_shader_vertex_state(),
-            primitive: default(),
-            depth_stencil: None,
-            multisample: MultisampleState {
+        let mut shader_defs = vec![];
+        if self.milky_way {
+            shader_defs.push("MILKY_WAY".into());
+        }
+        fullscreen_pipeline(
+            &self.layout,
+            &self.shader,
+            shader_defs,
+            MultisampleState {
                 count: key.msaa_samples,
                 ..default()
             },
-            fragment: Some(FragmentState {
-                shader: self.shader.clone(),
-                shader_defs: vec![],
-                entry_point: Cow::Borrowed("main"),
-                targets: vec![Some(ColorTargetState {
-                    format: TextureFormat::bevy_default(),
-                    blend: None,
-                    write_mask: ColorWrites::COLOR,
-                })],
-            }),
-            zero_initialize_workgroup_memory: true,
-        }
+        )
     }
 }
 
@@ -464,6 +1040,7 @@ fn prepare_sky_bind_group(
     specializer: Res<SkyPipelineSpecializer>,
     view_uniforms: Res<ViewUniforms>,
     globals_buffer: Res<GlobalsBuffer>,
+    environment_buffer: Res<EnvironmentBuffer>,
     mut commands: Commands,
 ) {
     let view_bindings = view_uniforms
@@ -474,10 +1051,18 @@ fn prepare_sky_bind_group(
         .buffer
         .binding()
         .expect("Could not create globals bindings for sky bind group");
+    let environment_binding = environment_buffer
+        .0
+        .binding()
+        .expect("Could not create environment bindings for sky bind group");
     let bind_group = rd.create_bind_group(
         "sky_bind_group",
         &specializer.layout,
-        &BindGroupEntries::with_indices(((3, view_bindings), (11, globals_binding))),
+        &BindGroupEntries::with_indices((
+            (3, view_bindings),
+            (11, globals_binding),
+            (ENVIRONMENT_BINDING, environment_binding),
+        )),
     );
     commands.entity(*cam).insert(SkyBindGroup(bind_group));
 }
@@ -526,163 +1111,14 @@ impl Plugin for WaterPlugin {
     fn build(&self, _app: &mut App) {}
 
     fn finish(&self, app: &mut App) {
-        app.get_sub_app_mut(RenderApp)
-            .expect("No RenderApp")
-            .init_resource::<WaterPipelineSpecializer>()
-            .init_resource::<SpecializedRenderPipelines<WaterPipelineSpecializer>>()
-            .add_systems(Render, queue_water_pipeline.in_set(RenderSet::Queue))
-            .add_render_graph_node::<ViewNodeRunner<RenderWaterNode>>(Core3d, RenderWaterLabel)
-            .add_render_graph_edges(
-                Core3d,
-                (
-                    Node3d::Tonemapping,
-                    RenderWaterLabel,
-                    Node3d::EndMainPassPostProcessing,
-                ),
-            );
+        add_post_process::<RenderWaterLabel>(app.get_sub_app_mut(RenderApp).expect("No RenderApp"));
     }
 }
 
-#[derive(Resource)]
-struct WaterPipelineSpecializer {
-    shader: Handle<Shader>,
-    layout: BindGroupLayout,
-    sampler: Sampler,
-}
-
-impl FromWorld for WaterPipelineSpecializer {
-    fn from_world(world: &mut World) -> Self {
-        let rd = world.resource::<RenderDevice>();
-        Self {
-            shader: world.load_asset("shaders/water.wgsl"),
-            layout: rd.create_bind_group_layout(
-                "water_bind_group_layout",
-                &BindGroupLayoutEntries::with_indices(
-                    ShaderStages::FRAGMENT,
-                    (
-                        (0, texture_2d_multisampled(TextureSampleType::Depth)),
-                        (
-                            1,
-                            texture_2d(TextureSampleType::Float { filterable: false }),
-                        ),
-                        (2, sampler(SamplerBindingType::NonFiltering)),
-                        (3, uniform_buffer::<ViewUniform>(true)),
-                        (11, uniform_buffer::<GlobalsUniform>(false)),
-                    ),
-                ),
-            ),
-            sampler: rd.create_sampler(&default()),
-        }
-    }
-}
-
-impl SpecializedRenderPipeline for WaterPipelineSpecializer {
-    type Key = ();
-
-    fn specialize(&self, _key: Self::Key) -> RenderPipelineDescriptor {
-        RenderPipelineDescriptor {
-            label: None,
-            layout: vec![self.layout.clone()],
-            push_constant_ranges: vec![],
-            vertex: fullscreen_shader_vertex_state(),
-            primitive: default(),
-            depth_stencil: None,
-            multisample: default(),
-            fragment: Some(FragmentState {
-                shader: self.shader.clone(),
-                shader_defs: vec![],
-                entry_point: Cow::Borrowed("main"),
-                targets: vec![Some(ColorTargetState {
-                    format: TextureFormat::bevy_default(),
-                    blend: None,
-                    write_mask: ColorWrites::COLOR,
-                })],
-            }),
-            zero_initialize_workgroup_memory: true,
-        }
-    }
-}
-
-#[derive(Component)]
-struct WaterPipelineId(CachedRenderPipelineId);
-
-fn queue_water_pipeline(
-    cam: Single<(Entity, &Msaa), With<Camera>>,
-    pipeline_cache: Res<PipelineCache>,
-    layouts: Res<WaterPipelineSpecializer>,
-    mut specializer: ResMut<SpecializedRenderPipelines<WaterPipelineSpecializer>>,
-    mut commands: Commands,
-) {
-    let id = specializer.specialize(&pipeline_cache, &layouts, ());
-    commands.entity(cam.0).insert(WaterPipelineId(id));
-}
-
-#[derive(RenderLabel, Hash, Debug, PartialEq, Eq, Clone)]
+#[derive(RenderLabel, Hash, Debug, PartialEq, Eq, Clone, Default)]
 struct RenderWaterLabel;
 
-#[derive(Default)]
-struct RenderWaterNode;
-
-impl ViewNode for RenderWaterNode {
-    type ViewQuery = (
-        Read<WaterPipelineId>,
-        Read<ViewTarget>,
-        Read<ViewDepthTexture>,
-        Read<ViewUniformOffset>,
-    );
-
-    fn run<'w>(
-        &self,
-        _graph: &mut RenderGraphContext,
-        render_context: &mut RenderContext<'w>,
-        (pipeline_id, view_target, view_depth_texture, view_uniform_offset): QueryItem<
-            'w,
-            Self::ViewQuery,
-        >,
-        world: &'w World,
-    ) -> Result<(), NodeRunError> {
-        let pipeline_cache = world.resource::<PipelineCache>();
-        let Some(pipeline) = pipeline_cache.get_render_pipeline(pipeline_id.0) else {
-            return Ok(());
-        };
-
-        let pipeline_specializer = world.resource::<WaterPipelineSpecializer>();
-        let post_process = view_target.post_process_write();
-        let view_bindings = world
-            .resource::<ViewUniforms>()
-            .uniforms
-            .binding()
-            .expect("Could not create view bindings for water bind group");
-        let globals_binding = world
-            .resource::<GlobalsBuffer>()
-            .buffer
-            .binding()
-            .expect("Could not create globals bindings for water bind group");
-        let bind_group = render_context.render_device().create_bind_group(
-            "water_bind_group",
-            &pipeline_specializer.layout,
-            &BindGroupEntries::with_indices((
-                (0, view_depth_texture.view()),
-                (1, post_process.source),
-                (2, &pipeline_specializer.sampler),
-                (3, view_bindings),
-                (11, globals_binding),
-            )),
-        );
-
-        let mut pass = render_context
-            .command_encoder()
-            .begin_render_pass(&RenderPassDescriptor {
-                color_attachments: &[Some(RenderPassColorAttachment {
-                    view: post_process.destination,
-                    resolve_target: None,
-                    ops: default(),
-                })],
-                ..default()
-            });
-        pass.set_pipeline(pipeline);
-        pass.set_bind_group(0, &bind_group, &[view_uniform_offset.offset]);
-        pass.draw(0..3, 0..1);
-        Ok(())
-    }
+impl PostProcess for RenderWaterLabel {
+    const NAME: &'static str = "water";
+    const SHADER: &'static str = "shaders/water.wgsl";
 }
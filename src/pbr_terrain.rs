@@ -0,0 +1,132 @@
+//! Optional shading of the terrain through `bevy_pbr`, lit by directional lights standing in
+//! for the sun and moon. The displacement stays in `terrain_vertex.wgsl`.
+
+use bevy::{
+    pbr::{ExtendedMaterial, MaterialExtension},
+    prelude::*,
+    render::render_resource::ShaderRef,
+};
+
+use crate::{
+    TerrainMaterial, TerrainMaterialHandle,
+    environment::{EnvironmentUniform, MOON_BRIGHTNESS, map_sky_height, update_environment},
+    setup, update_chunks,
+};
+
+pub type PbrTerrainMaterial = ExtendedMaterial<StandardMaterial, TerrainMaterial>;
+
+pub struct PbrTerrainPlugin;
+
+impl Plugin for PbrTerrainPlugin {
+    fn build(&self, app: &mut App) {
+        app.add_plugins(MaterialPlugin::<PbrTerrainMaterial>::default())
+            .add_systems(
+                Startup,
+                (setup_pbr_terrain, spawn_celestial_lights)
+                    .after(setup)
+                    .before(update_chunks),
+            )
+            .add_systems(
+                PostUpdate,
+                (
+                    sync_pbr_terrain_material.run_if(resource_changed::<Assets<TerrainMaterial>>),
+                    update_celestial_lights
+                        .after(update_environment)
+                        .before(TransformSystem::TransformPropagate)
+                        .run_if(resource_changed::<EnvironmentUniform>),
+                ),
+            );
+    }
+}
+
+impl MaterialExtension for TerrainMaterial {
+    fn vertex_shader() -> ShaderRef {
+        "shaders/terrain.wgsl".into()
+    }
+
+    fn fragment_shader() -> ShaderRef {
+        "shaders/terrain_pbr.wgsl".into()
+    }
+
+    // The shadows have to be cast by the displaced terrain
+    fn prepass_vertex_shader() -> ShaderRef {
+        "shaders/terrain_prepass.wgsl".into()
+    }
+}
+
+/// Takes the place of [`TerrainMaterialHandle`] on the chunks when present.
+#[derive(Resource)]
+pub struct PbrTerrainMaterialHandle(pub Handle<PbrTerrainMaterial>);
+
+fn setup_pbr_terrain(
+    mut commands: Commands,
+    terrain_material: Res<TerrainMaterialHandle>,
+    terrain_materials: Res<Assets<TerrainMaterial>>,
+    mut materials: ResMut<Assets<PbrTerrainMaterial>>,
+) {
+    let Some(extension) = terrain_materials.get(&terrain_material.0) else {
+        return;
+    };
+    commands.insert_resource(PbrTerrainMaterialHandle(materials.add(
+        PbrTerrainMaterial {
+            base: StandardMaterial {
+                perceptual_roughness: 0.9,
+                reflectance: 0.2,
+                ..default()
+            },
+            extension: extension.clone(),
+        },
+    )));
+}
+
+/// The rest of the game updates [`TerrainMaterial`], which is passed on here.
+fn sync_pbr_terrain_material(
+    terrain_material: Res<TerrainMaterialHandle>,
+    terrain_materials: Res<Assets<TerrainMaterial>>,
+    material: Res<PbrTerrainMaterialHandle>,
+    mut materials: ResMut<Assets<PbrTerrainMaterial>>,
+) {
+    if let Some(extension) = terrain_materials.get(&terrain_material.0)
+        && let Some(material) = materials.get_mut(&material.0)
+    {
+        material.extension = extension.clone();
+    }
+}
+
+/// Lights the terrain about as brightly as the custom shading, at the camera's default exposure.
+const SUN_ILLUMINANCE: f32 = 1000.0;
+
+#[derive(Component)]
+struct SunLight;
+
+#[derive(Component)]
+struct MoonLight;
+
+fn spawn_celestial_lights(mut commands: Commands) {
+    commands.spawn((
+        SunLight,
+        DirectionalLight {
+            shadows_enabled: true,
+            ..default()
+        },
+    ));
+    // Too faint for shadows to be worth their cost
+    commands.spawn((MoonLight, DirectionalLight::default()));
+}
+
+fn update_celestial_lights(
+    environment: Res<EnvironmentUniform>,
+    mut sun: Single<(&mut DirectionalLight, &mut Transform), (With<SunLight>, Without<MoonLight>)>,
+    mut moon: Single<(&mut DirectionalLight, &mut Transform), (With<MoonLight>, Without<SunLight>)>,
+) {
+    let (sun_light, sun_tf) = &mut *sun;
+    sun_light.illuminance = SUN_ILLUMINANCE * map_sky_height(environment.sun_dir.y);
+    **sun_tf = Transform::default().looking_to(-environment.sun_dir, Vec3::Y);
+
+    let (moon_light, moon_tf) = &mut *moon;
+    moon_light.illuminance = SUN_ILLUMINANCE
+        * map_sky_height(environment.moon_dir.y)
+        * MOON_BRIGHTNESS
+        * environment.moon_illumination;
+    **moon_tf = Transform::default().looking_to(-environment.moon_dir, Vec3::Y);
+}
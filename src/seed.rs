@@ -0,0 +1,33 @@
+use bevy::prelude::*;
+
+use crate::height_field::TerrainHeightField;
+
+/// Seed of every procedural pattern in the world: terrain, clouds and water waves.
+///
+/// The same seed always produces the same world.
+#[derive(Resource, Clone, Copy, Default, Debug, PartialEq, Eq)]
+pub struct WorldSeed(pub u32);
+
+/// PCG hash, identical to `common::hash` in `common.wgsl`.
+///
+/// <https://www.jcgt.org/published/0009/03/02/>
+pub fn hash(x: u32) -> u32 {
+    let state = x.wrapping_mul(747796405).wrapping_add(2891336453);
+    let word = ((state >> ((state >> 28) + 4)) ^ state).wrapping_mul(277803737);
+    (word >> 22) ^ word
+}
+
+/// Offset into the noise domain in `[0, 1024)²`, picking a different region for each seed.
+///
+/// `salt` tells apart the patterns sharing one seed, like the octaves of the terrain.
+/// Identical to `common::seed_offset` in `common.wgsl`.
+pub fn seed_offset(seed: u32, salt: u32) -> Vec2 {
+    let h = hash(seed ^ hash(salt));
+    // Dividing by a power of two is exact, so the GPU gets the same result
+    Vec2::new((h & 0xffff) as f32, (h >> 16) as f32) / 64.0
+}
+
+/// Passes the seed on to the terrain, whose noise settings are also used on the CPU.
+pub fn apply_world_seed(seed: Res<WorldSeed>, mut height_field: ResMut<TerrainHeightField>) {
+    height_field.noise.seed = seed.0;
+}
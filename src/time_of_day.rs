@@ -0,0 +1,269 @@
+use std::f32::consts::TAU;
+
+use bevy::{prelude::*, ui::RelativeCursorPosition};
+
+use crate::AppState;
+
+/// Drives the day cycle and lets it be paused, sped up or set.
+pub struct TimeOfDayPlugin;
+
+impl Plugin for TimeOfDayPlugin {
+    fn build(&self, app: &mut App) {
+        app.init_resource::<TimeOfDay>().add_systems(
+            Update,
+            (
+                (control_time_of_day, advance_time_of_day)
+                    .chain()
+                    .run_if(in_state(AppState::Running)),
+                drag_time_of_day_slider,
+                update_time_of_day_slider.run_if(resource_changed::<TimeOfDay>),
+            )
+                .chain(),
+        );
+    }
+}
+
+#[derive(Resource, Clone, Debug)]
+pub struct TimeOfDay {
+    /// Local solar time in `[0, 24)`, 12 being noon
+    pub hour: f32,
+    /// Day of the year, starting at 0 on January 1
+    pub day: u32,
+    pub year: i32,
+    /// Seconds per full day when `time_scale` is 1
+    pub day_length: f32,
+    pub time_scale: f32,
+    pub paused: bool,
+}
+
+impl Default for TimeOfDay {
+    fn default() -> Self {
+        Self {
+            hour: 12.0,
+            // Summer solstice, for long days
+            day: 171,
+            year: 2000,
+            day_length: 100.0 * TAU,
+            time_scale: 1.0,
+            paused: false,
+        }
+    }
+}
+
+impl TimeOfDay {
+    /// Moves forward (or backward) in time, turning over the day and year as needed.
+    pub fn advance(&mut self, hours: f32) {
+        let hour = self.hour + hours;
+        self.hour = hour.rem_euclid(24.0);
+        let mut day = self.day as i32 + hour.div_euclid(24.0) as i32;
+        while day < 0 {
+            self.year -= 1;
+            day += days_in_year(self.year);
+        }
+        while day >= days_in_year(self.year) {
+            day -= days_in_year(self.year);
+            self.year += 1;
+        }
+        self.day = day as u32;
+    }
+
+    /// Days since the J2000 epoch, noon on January 1, 2000.
+    pub fn days_since_j2000(&self) -> f64 {
+        let leap_days =
+            |year: i32| year.div_euclid(4) - year.div_euclid(100) + year.div_euclid(400);
+        let days_before_year =
+            365 * (self.year - 2000) + leap_days(self.year - 1) - leap_days(1999);
+        (days_before_year + self.day as i32) as f64 + (self.hour as f64 - 12.0) / 24.0
+    }
+
+    /// Formatted as `hh:mm`.
+    pub fn clock(&self) -> String {
+        let minutes = (self.hour * 60.0) as u32;
+        format!("{:02}:{:02}", minutes / 60 % 24, minutes % 60)
+    }
+}
+
+fn days_in_year(year: i32) -> i32 {
+    if year % 4 == 0 && (year % 100 != 0 || year % 400 == 0) {
+        366
+    } else {
+        365
+    }
+}
+
+/// How fast holding `,` or `.` scrubs through the day, in hours per second.
+const SCRUB_SPEED: f32 = 4.0;
+
+fn control_time_of_day(
+    kb: Res<ButtonInput<KeyCode>>,
+    mut time_of_day: ResMut<TimeOfDay>,
+    time: Res<Time<Real>>,
+) {
+    if kb.just_pressed(KeyCode::KeyT) {
+        time_of_day.paused = !time_of_day.paused;
+    }
+    if kb.just_pressed(KeyCode::BracketLeft) {
+        time_of_day.time_scale /= 2.0;
+    }
+    if kb.just_pressed(KeyCode::BracketRight) {
+        time_of_day.time_scale *= 2.0;
+    }
+    let mut scrub = 0.0;
+    if kb.pressed(KeyCode::Comma) {
+        scrub -= SCRUB_SPEED;
+    }
+    if kb.pressed(KeyCode::Period) {
+        scrub += SCRUB_SPEED;
+    }
+    if scrub != 0.0 {
+        time_of_day.advance(scrub * time.delta_secs());
+    }
+}
+
+fn advance_time_of_day(mut time_of_day: ResMut<TimeOfDay>, time: Res<Time>) {
+    if time_of_day.paused {
+        return;
+    }
+    let hours = time.delta_secs() * time_of_day.time_scale * 24.0 / time_of_day.day_length;
+    time_of_day.advance(hours);
+}
+
+/// Track of the time of day slider in the pause menu.
+#[derive(Component)]
+#[require(Interaction, RelativeCursorPosition)]
+pub struct TimeOfDaySlider;
+
+/// Part of the slider filled up to the current hour.
+#[derive(Component)]
+pub struct TimeOfDaySliderFill;
+
+#[derive(Component)]
+pub struct TimeOfDayText;
+
+/// Slider setting the time of day, for the pause menu.
+pub fn time_of_day_slider(time_of_day: &TimeOfDay) -> impl Bundle {
+    (
+        Node {
+            width: Val::Percent(70.0),
+            flex_direction: FlexDirection::Column,
+            align_items: AlignItems::Center,
+            ..default()
+        },
+        children![
+            (
+                Text::new(time_of_day_text(time_of_day)),
+                TextFont::from_font_size(30.0),
+                TimeOfDayText,
+            ),
+            (
+                TimeOfDaySlider,
+                Node {
+                    width: Val::Percent(100.0),
+                    height: Val::Px(20.0),
+                    ..default()
+                },
+                BackgroundColor(Color::BLACK),
+                BorderRadius::all(Val::Percent(100.0)),
+                children![(
+                    TimeOfDaySliderFill,
+                    Node {
+                        width: Val::Percent(time_of_day.hour / 24.0 * 100.0),
+                        height: Val::Percent(100.0),
+                        ..default()
+                    },
+                    BackgroundColor(Color::srgb(0.4, 0.4, 0.4)),
+                    BorderRadius::all(Val::Percent(100.0)),
+                )],
+            ),
+        ],
+    )
+}
+
+fn time_of_day_text(time_of_day: &TimeOfDay) -> String {
+    format!(
+        "Time of day: {}, day {} of {}",
+        time_of_day.clock(),
+        time_of_day.day + 1,
+        time_of_day.year,
+    )
+}
+
+fn drag_time_of_day_slider(
+    q: Query<(&Interaction, &RelativeCursorPosition), With<TimeOfDaySlider>>,
+    mut time_of_day: ResMut<TimeOfDay>,
+) {
+    for (interaction, cursor) in &q {
+        if *interaction == Interaction::Pressed
+            && let Some(pos) = cursor.normalized
+        {
+            // Stop short of 24, which would wrap around to 0
+            time_of_day.hour = (pos.x * 24.0).clamp(0.0, 23.99);
+        }
+    }
+}
+
+fn update_time_of_day_slider(
+    time_of_day: Res<TimeOfDay>,
+    mut fill: Query<&mut Node, With<TimeOfDaySliderFill>>,
+    mut text: Query<&mut Text, With<TimeOfDayText>>,
+) {
+    for mut node in &mut fill {
+        node.width = Val::Percent(time_of_day.hour / 24.0 * 100.0);
+    }
+    for mut text in &mut text {
+        text.0 = time_of_day_text(&time_of_day);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn at(year: i32, day: u32, hour: f32) -> TimeOfDay {
+        TimeOfDay {
+            hour,
+            day,
+            year,
+            ..default()
+        }
+    }
+
+    #[test]
+    fn advance_turns_over_the_year() {
+        let mut time = at(1999, 364, 23.0);
+        time.advance(2.0);
+        assert_eq!((time.year, time.day), (2000, 0));
+        assert!((time.hour - 1.0).abs() < 1e-4);
+    }
+
+    #[test]
+    fn advance_counts_leap_days() {
+        // February 28 and 29 of a leap year
+        let mut time = at(2024, 58, 12.0);
+        time.advance(24.0);
+        assert_eq!((time.year, time.day), (2024, 59));
+        time.advance(24.0);
+        assert_eq!((time.year, time.day), (2024, 60));
+        // Not in a year divisible by 100 but not by 400
+        let mut time = at(2100, 58, 12.0);
+        time.advance(24.0);
+        assert_eq!(days_in_year(2100), 365);
+        assert_eq!((time.year, time.day), (2100, 59));
+    }
+
+    #[test]
+    fn advance_backwards_turns_back_the_year() {
+        let mut time = at(2001, 0, 1.0);
+        time.advance(-2.0);
+        // 2000 being a leap year
+        assert_eq!((time.year, time.day), (2000, 365));
+        assert!((time.hour - 23.0).abs() < 1e-4);
+    }
+
+    #[test]
+    fn j2000_is_noon_on_january_1_2000() {
+        assert_eq!(at(2000, 0, 12.0).days_since_j2000(), 0.0);
+        assert_eq!(at(2001, 0, 12.0).days_since_j2000(), 366.0);
+        assert_eq!(at(1999, 364, 12.0).days_since_j2000(), -1.0);
+    }
+}
@@ -0,0 +1,400 @@
+use bevy::prelude::*;
+use clap::ValueEnum;
+use serde::Deserialize;
+
+use crate::{
+    AppState,
+    environment::{CloudSettings, EnvironmentUniform, map_sky_height, sky_brightness},
+    seed::{WorldSeed, hash},
+};
+
+/// Blends between kinds of weather and rains or snows around the camera.
+pub struct WeatherPlugin;
+
+impl Plugin for WeatherPlugin {
+    fn build(&self, app: &mut App) {
+        app.init_resource::<Weather>()
+            .add_systems(Startup, spawn_precipitation)
+            .add_systems(
+                Update,
+                (
+                    (cycle_weather, change_weather, transition_weather)
+                        .chain()
+                        .run_if(in_state(AppState::Running)),
+                    update_precipitation,
+                    update_precipitation_color.run_if(resource_changed::<EnvironmentUniform>),
+                )
+                    .chain(),
+            );
+    }
+}
+
+#[derive(Deserialize, ValueEnum, Clone, Copy, PartialEq, Eq, Debug)]
+pub enum WeatherKind {
+    Clear,
+    Cloudy,
+    Overcast,
+    Rain,
+    Snow,
+    Fog,
+}
+
+impl WeatherKind {
+    const ALL: [Self; 6] = [
+        Self::Clear,
+        Self::Cloudy,
+        Self::Overcast,
+        Self::Rain,
+        Self::Snow,
+        Self::Fog,
+    ];
+
+    pub fn conditions(self) -> WeatherConditions {
+        let clear = WeatherConditions {
+            cloud_coverage: 0.2,
+            fog_density: 0.0,
+            wave_strength: 0.6,
+            rain: 0.0,
+            snow: 0.0,
+        };
+        match self {
+            Self::Clear => clear,
+            Self::Cloudy => WeatherConditions {
+                cloud_coverage: 0.5,
+                wave_strength: 1.0,
+                ..clear
+            },
+            Self::Overcast => WeatherConditions {
+                cloud_coverage: 0.9,
+                fog_density: 0.0005,
+                wave_strength: 1.3,
+                ..clear
+            },
+            Self::Rain => WeatherConditions {
+                cloud_coverage: 1.0,
+                fog_density: 0.002,
+                wave_strength: 1.8,
+                rain: 1.0,
+                ..clear
+            },
+            Self::Snow => WeatherConditions {
+                cloud_coverage: 0.95,
+                fog_density: 0.003,
+                wave_strength: 0.8,
+                snow: 1.0,
+                ..clear
+            },
+            Self::Fog => WeatherConditions {
+                cloud_coverage: 0.6,
+                fog_density: 0.02,
+                wave_strength: 0.3,
+                ..clear
+            },
+        }
+    }
+
+    fn next(self) -> Self {
+        let i = Self::ALL.iter().position(|&kind| kind == self).unwrap_or(0);
+        Self::ALL[(i + 1) % Self::ALL.len()]
+    }
+}
+
+/// What the shaders and the precipitation see of the weather.
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub struct WeatherConditions {
+    /// Fraction of the sky covered by clouds, from 0 to 1
+    pub cloud_coverage: f32,
+    /// Extinction of the fog per unit of distance, on top of the atmosphere's haze
+    pub fog_density: f32,
+    /// Multiplies the height of the water waves
+    pub wave_strength: f32,
+    /// Fraction of the raindrops falling, from 0 to 1
+    pub rain: f32,
+    /// Fraction of the snowflakes falling, from 0 to 1
+    pub snow: f32,
+}
+
+impl WeatherConditions {
+    fn max_difference(self, other: Self) -> f32 {
+        [
+            self.cloud_coverage - other.cloud_coverage,
+            self.fog_density - other.fog_density,
+            self.wave_strength - other.wave_strength,
+            self.rain - other.rain,
+            self.snow - other.snow,
+        ]
+        .into_iter()
+        .fold(0.0, |max, difference| max.max(difference.abs()))
+    }
+
+    fn lerp(self, other: Self, t: f32) -> Self {
+        Self {
+            cloud_coverage: self.cloud_coverage.lerp(other.cloud_coverage, t),
+            fog_density: self.fog_density.lerp(other.fog_density, t),
+            wave_strength: self.wave_strength.lerp(other.wave_strength, t),
+            rain: self.rain.lerp(other.rain, t),
+            snow: self.snow.lerp(other.snow, t),
+        }
+    }
+}
+
+#[derive(Deserialize, Clone, Debug)]
+pub struct WeatherSettings {
+    /// Seconds for most of a change in the weather to happen
+    pub transition_time: f32,
+    /// Seconds between random changes in the weather, if any
+    pub change_interval: Option<f32>,
+}
+
+impl Default for WeatherSettings {
+    fn default() -> Self {
+        Self {
+            transition_time: 20.0,
+            change_interval: Some(600.0),
+        }
+    }
+}
+
+impl WeatherSettings {
+    /// Checks the settings loaded from the world settings, which the weather changes rely on.
+    pub fn validate(&self) -> Result<(), String> {
+        if !self.transition_time.is_finite() || self.transition_time <= 0.0 {
+            return Err(format!(
+                "transition_time isn't a positive number: {}",
+                self.transition_time
+            ));
+        }
+        if let Some(interval) = self.change_interval
+            && (!interval.is_finite() || interval <= 0.0)
+        {
+            return Err(format!(
+                "change_interval isn't a positive number: {interval}"
+            ));
+        }
+        Ok(())
+    }
+}
+
+#[derive(Resource, Clone, Debug)]
+pub struct Weather {
+    /// What the weather is turning into
+    pub kind: WeatherKind,
+    /// Where the transition to `kind` is at
+    pub conditions: WeatherConditions,
+    pub settings: WeatherSettings,
+    /// Seconds until the next random change
+    pub until_change: f32,
+    /// Random changes so far, picking the next one
+    pub changes: u32,
+}
+
+impl Weather {
+    /// Already settled into `kind`, without a transition.
+    pub fn new(kind: WeatherKind) -> Self {
+        let settings = WeatherSettings::default();
+        Self {
+            kind,
+            conditions: kind.conditions(),
+            until_change: settings.change_interval.unwrap_or(0.0),
+            settings,
+            changes: 0,
+        }
+    }
+}
+
+impl Default for Weather {
+    fn default() -> Self {
+        Self::new(WeatherKind::Clear)
+    }
+}
+
+fn cycle_weather(kb: Res<ButtonInput<KeyCode>>, mut weather: ResMut<Weather>) {
+    if kb.just_pressed(KeyCode::KeyR) {
+        weather.kind = weather.kind.next();
+        weather.until_change = weather.settings.change_interval.unwrap_or(0.0);
+    }
+}
+
+/// Picks a random new weather every `change_interval` seconds.
+fn change_weather(mut weather: ResMut<Weather>, seed: Res<WorldSeed>, time: Res<Time>) {
+    let Some(interval) = weather.settings.change_interval else {
+        return;
+    };
+    // Counting down isn't a change anyone needs to react to
+    weather.bypass_change_detection().until_change -= time.delta_secs();
+    if weather.until_change > 0.0 {
+        return;
+    }
+    weather.until_change = interval;
+    weather.changes += 1;
+    let i = hash(seed.0 ^ hash(weather.changes)) as usize % WeatherKind::ALL.len();
+    weather.kind = WeatherKind::ALL[i];
+}
+
+/// Below this difference in any of the conditions, the transition snaps to the target.
+const TRANSITION_EPSILON: f32 = 1e-6;
+
+fn transition_weather(mut weather: ResMut<Weather>, time: Res<Time>) {
+    let target = weather.kind.conditions();
+    if weather.conditions == target {
+        return;
+    }
+    let t = 1.0 - (-time.delta_secs() / weather.settings.transition_time).exp();
+    let conditions = weather.conditions.lerp(target, t);
+    // The approach is exponential and would never quite arrive
+    weather.conditions = if conditions.max_difference(target) < TRANSITION_EPSILON {
+        target
+    } else {
+        conditions
+    };
+}
+
+/// Drops and flakes fall within this distance of the camera, horizontally and vertically.
+const PRECIPITATION_RANGE: Vec3 = Vec3::new(30.0, 20.0, 30.0);
+/// Of each kind, all of them falling at full strength
+const MAX_PARTICLES: u32 = 1500;
+/// Fraction of the cloud layer's wind felt near the ground
+const GROUND_WIND: f32 = 0.3;
+
+#[derive(Component, Clone, Copy, PartialEq)]
+enum Precipitation {
+    Rain,
+    Snow,
+}
+
+impl Precipitation {
+    /// In units per second
+    fn fall_speed(self) -> f32 {
+        match self {
+            Self::Rain => 9.0,
+            Self::Snow => 1.0,
+        }
+    }
+}
+
+/// Which of the particles of its kind this is, deciding whether it falls at a given strength.
+#[derive(Component)]
+struct ParticleIndex(u32);
+
+fn spawn_precipitation(
+    mut commands: Commands,
+    mut meshes: ResMut<Assets<Mesh>>,
+    mut materials: ResMut<Assets<StandardMaterial>>,
+) {
+    let kinds = [
+        (
+            Precipitation::Rain,
+            meshes.add(Cuboid::new(0.01, 0.4, 0.01)),
+            Color::srgb(0.5, 0.55, 0.6),
+        ),
+        (
+            Precipitation::Snow,
+            meshes.add(Cuboid::from_length(0.04)),
+            Color::WHITE,
+        ),
+    ];
+    let mut precipitation_materials = Vec::new();
+    for (kind, mesh, color) in kinds {
+        let material = materials.add(StandardMaterial {
+            base_color: color,
+            // Darkened by `update_precipitation_color` instead, the same with or without the
+            // lights of `--pbr`
+            unlit: true,
+            ..default()
+        });
+        precipitation_materials.push((material.clone(), color));
+        for i in 0..MAX_PARTICLES {
+            // Scattered through the range, kept there as the camera moves
+            let salt = i * 3 + kind as u32 * MAX_PARTICLES * 3;
+            let unit = |salt: u32| hash(salt) as f32 / u32::MAX as f32;
+            let pos = Vec3::new(unit(salt), unit(salt + 1), unit(salt + 2)) * 2.0 - 1.0;
+            commands.spawn((
+                kind,
+                ParticleIndex(i),
+                Mesh3d(mesh.clone()),
+                MeshMaterial3d(material.clone()),
+                Transform::from_translation(pos * PRECIPITATION_RANGE),
+                Visibility::Hidden,
+            ));
+        }
+    }
+    commands.insert_resource(PrecipitationMaterials(precipitation_materials));
+}
+
+/// With the color each has in full daylight
+#[derive(Resource)]
+struct PrecipitationMaterials(Vec<(Handle<StandardMaterial>, Color)>);
+
+/// Below this change in brightness, the precipitation keeps its color. Changing the materials
+/// has them prepared anew, which isn't worth it every frame for a difference nobody sees.
+const BRIGHTNESS_STEP: f32 = 1.0 / 256.0;
+
+/// Darkens the precipitation at night, like `common::sky_brightness` does the water.
+fn update_precipitation_color(
+    environment: Res<EnvironmentUniform>,
+    precipitation_materials: Res<PrecipitationMaterials>,
+    mut materials: ResMut<Assets<StandardMaterial>>,
+    mut shown_brightness: Local<f32>,
+) {
+    let brightness = sky_brightness(
+        map_sky_height(environment.sun_dir.y),
+        map_sky_height(environment.moon_dir.y),
+        environment.moon_illumination,
+    );
+    // Never below 0.1, so the first run always gets here
+    if (brightness - *shown_brightness).abs() < BRIGHTNESS_STEP {
+        return;
+    }
+    *shown_brightness = brightness;
+    for (handle, color) in &precipitation_materials.0 {
+        if let Some(material) = materials.get_mut(handle) {
+            material.base_color = (color.to_linear() * brightness).into();
+        }
+    }
+}
+
+fn update_precipitation(
+    weather: Res<Weather>,
+    clouds: Res<CloudSettings>,
+    time: Res<Time>,
+    cam: Single<&Transform, (With<Camera>, Without<Precipitation>)>,
+    mut q: Query<(
+        &Precipitation,
+        &ParticleIndex,
+        &mut Transform,
+        &mut Visibility,
+    )>,
+) {
+    let wind = clouds.wind * GROUND_WIND;
+    for (&kind, index, mut tf, mut visibility) in &mut q {
+        let strength = match kind {
+            Precipitation::Rain => weather.conditions.rain,
+            Precipitation::Snow => weather.conditions.snow,
+        };
+        let falling = (index.0 as f32) < strength * MAX_PARTICLES as f32;
+        visibility.set_if_neq(if falling {
+            Visibility::Inherited
+        } else {
+            Visibility::Hidden
+        });
+        if !falling {
+            continue;
+        }
+        let velocity = Vec3::new(wind.x, -kind.fall_speed(), wind.y);
+        let mut pos = tf.translation + velocity * time.delta_secs();
+        if kind == Precipitation::Snow {
+            // Flutter about on the way down
+            let phase = time.elapsed_secs() + index.0 as f32;
+            pos.x += phase.sin() * time.delta_secs() * 0.5;
+        }
+        // Wrap around to stay within range of the camera
+        let offset = pos - cam.translation;
+        let offset = (offset + PRECIPITATION_RANGE).rem_euclid(2.0 * PRECIPITATION_RANGE)
+            - PRECIPITATION_RANGE;
+        tf.translation = cam.translation + offset;
+        // Streaks along the direction of travel
+        if kind == Precipitation::Rain {
+            tf.rotation = Quat::from_rotation_arc(Vec3::NEG_Y, velocity.normalize());
+        }
+    }
+}
@@ -0,0 +1,151 @@
+use bevy::{
+    asset::{AssetLoader, LoadContext, io::Reader},
+    prelude::*,
+};
+use serde::Deserialize;
+
+use crate::{
+    FlyCollision, MouseSensitivity, TerrainShading, WalkSettings,
+    celestial::Observer,
+    cli::Cli,
+    environment::CloudSettings,
+    height_field::TerrainHeightField,
+    height_field::TerrainNoiseSettings,
+    lod::LodSettings,
+    seed::WorldSeed,
+    weather::{Weather, WeatherSettings},
+};
+
+/// Loads [`WorldSettings`] from [`WORLD_SETTINGS_PATH`] and applies them
+/// whenever the file is (re)loaded.
+pub struct WorldSettingsPlugin;
+
+impl Plugin for WorldSettingsPlugin {
+    fn build(&self, app: &mut App) {
+        app.init_asset::<WorldSettings>()
+            .init_asset_loader::<WorldSettingsLoader>()
+            .add_systems(Startup, load_world_settings)
+            .add_systems(Update, apply_world_settings);
+    }
+}
+
+const WORLD_SETTINGS_PATH: &str = "default.world.ron";
+
+/// Everything that can differ between machines and demos without rebuilding.
+#[derive(Asset, TypePath, Deserialize)]
+pub struct WorldSettings {
+    seed: u32,
+    chunks: LodSettings,
+    noise: TerrainNoiseSettings,
+    shading: TerrainShading,
+    mouse_sensitivity: Vec2,
+    walk: WalkSettings,
+    fly_collision: FlyCollision,
+    observer: Observer,
+    clouds: CloudSettings,
+    weather: WeatherSettings,
+}
+
+#[derive(Default)]
+struct WorldSettingsLoader;
+
+impl AssetLoader for WorldSettingsLoader {
+    type Asset = WorldSettings;
+    type Settings = ();
+    type Error = Box<dyn std::error::Error + Send + Sync>;
+
+    async fn load(
+        &self,
+        reader: &mut dyn Reader,
+        _settings: &(),
+        _load_context: &mut LoadContext<'_>,
+    ) -> Result<WorldSettings, Self::Error> {
+        let mut bytes = Vec::new();
+        reader.read_to_end(&mut bytes).await?;
+        let settings: WorldSettings = ron::de::from_bytes(&bytes)?;
+        // Failing the load keeps the previous settings
+        settings.chunks.validate()?;
+        settings.noise.validate()?;
+        settings.shading.validate()?;
+        settings.clouds.validate()?;
+        settings.weather.validate()?;
+        Ok(settings)
+    }
+
+    fn extensions(&self) -> &[&str] {
+        &["world.ron"]
+    }
+}
+
+#[derive(Resource)]
+struct WorldSettingsHandle(Handle<WorldSettings>);
+
+fn load_world_settings(mut commands: Commands, asset_server: Res<AssetServer>) {
+    commands.insert_resource(WorldSettingsHandle(asset_server.load(WORLD_SETTINGS_PATH)));
+}
+
+fn apply_world_settings(
+    mut events: EventReader<AssetEvent<WorldSettings>>,
+    handle: Res<WorldSettingsHandle>,
+    settings: Res<Assets<WorldSettings>>,
+    mut commands: Commands,
+    mut height_field: ResMut<TerrainHeightField>,
+    mut weather: ResMut<Weather>,
+    mut fly_collision: ResMut<FlyCollision>,
+    mut lod_settings: ResMut<LodSettings>,
+    mut observer: ResMut<Observer>,
+    mut clouds: ResMut<CloudSettings>,
+    mut shading: ResMut<TerrainShading>,
+    mut loaded_seed: Local<Option<u32>>,
+    mut loaded_fly_collision: Local<Option<bool>>,
+    cli: Res<Cli>,
+) {
+    for event in events.read() {
+        let (AssetEvent::Added { id } | AssetEvent::Modified { id }) = *event else {
+            continue;
+        };
+        if id != handle.0.id() {
+            continue;
+        }
+        let Some(settings) = settings.get(id) else {
+            continue;
+        };
+        // Keep a seed picked in the pause menu while tweaking other settings
+        if cli.seed.is_none() && *loaded_seed != Some(settings.seed) {
+            *loaded_seed = Some(settings.seed);
+            commands.insert_resource(WorldSeed(settings.seed));
+        }
+        // Only if it differs, as a changed `LodSettings` respawns every chunk
+        lod_settings.set_if_neq(LodSettings {
+            render_dist: cli.render_dist.unwrap_or(settings.chunks.render_dist),
+            ..settings.chunks.clone()
+        });
+        commands.insert_resource(MouseSensitivity(settings.mouse_sensitivity));
+        commands.insert_resource(settings.walk.clone());
+        // Keep collision toggled at runtime while tweaking other settings
+        if *loaded_fly_collision != Some(settings.fly_collision.enabled) {
+            *loaded_fly_collision = Some(settings.fly_collision.enabled);
+            fly_collision.enabled = settings.fly_collision.enabled;
+        }
+        fly_collision.clearance = settings.fly_collision.clearance;
+        observer.set_if_neq(Observer {
+            latitude: cli.latitude.unwrap_or(settings.observer.latitude),
+        });
+        clouds.set_if_neq(settings.clouds.clone());
+        // Keep the countdown to the next random weather unless its interval changed
+        if weather.settings.change_interval != settings.weather.change_interval {
+            weather.until_change = settings.weather.change_interval.unwrap_or(0.0);
+        }
+        weather.settings = settings.weather.clone();
+        // `update_terrain_noise` passes this on to the material
+        let noise = TerrainNoiseSettings {
+            seed: height_field.noise.seed,
+            ..settings.noise
+        };
+        if height_field.noise != noise {
+            height_field.noise = noise;
+        }
+        // `update_terrain_shading` passes this on to the material
+        shading.set_if_neq(settings.shading);
+    }
+}
@@ -0,0 +1,212 @@
+"""Generates the tileable terrain layer textures in `assets/textures`.
+
+Writes one KTX2 texture array each for the albedo (sRGB), normal (X and Y) and roughness of
+the layers, with all mip levels. Pure Python, so it runs anywhere without dependencies:
+
+    python3 tools/terrain_textures.py
+
+Keep the order of `LAYERS` in sync with the layer indices in `terrain_layers.wgsl`.
+"""
+
+import math, random, struct, os
+
+SIZE = 256
+LAYERS = ['grass', 'rock', 'dirt', 'sand', 'snow']
+OUT = os.path.join(os.path.dirname(os.path.abspath(__file__)), '..', 'assets', 'textures')
+# Average linear albedo of every layer, scaled back to the layer's color by `albedo_scale`
+# in `terrain_layers.wgsl`
+ALBEDO_MEAN = 0.5
+
+def lattice(period, seed):
+    rng = random.Random(seed)
+    return [[rng.random() for _ in range(period)] for _ in range(period)]
+
+def smooth(t):
+    return t * t * (3 - 2 * t)
+
+def value_noise(grid, period, u, v):
+    x = u * period; y = v * period
+    x0 = int(math.floor(x)); y0 = int(math.floor(y))
+    fx = smooth(x - x0); fy = smooth(y - y0)
+    x0 %= period; y0 %= period
+    x1 = (x0 + 1) % period; y1 = (y0 + 1) % period
+    a = grid[y0][x0]; b = grid[y0][x1]; c = grid[y1][x0]; d = grid[y1][x1]
+    return (a + (b - a) * fx) + ((c + (d - c) * fx) - (a + (b - a) * fx)) * fy
+
+class Fbm:
+    def __init__(self, base, octaves, seed, ridged=False, gain=0.5):
+        self.grids = [(base << i, lattice(base << i, seed * 31 + i)) for i in range(octaves)]
+        self.ridged = ridged; self.gain = gain
+    def __call__(self, u, v):
+        total = 0; amp = 1; norm = 0
+        for period, grid in self.grids:
+            n = value_noise(grid, period, u, v)
+            if self.ridged:
+                n = 1 - abs(2 * n - 1)
+            total += n * amp; norm += amp; amp *= self.gain
+        return total / norm
+
+def cells(period, seed):
+    rng = random.Random(seed)
+    return [[(rng.random(), rng.random()) for _ in range(period)] for _ in range(period)]
+
+def worley(pts, period, u, v):
+    x = u * period; y = v * period
+    cx = int(math.floor(x)); cy = int(math.floor(y))
+    d1 = d2 = 9.0
+    for oy in (-1, 0, 1):
+        for ox in (-1, 0, 1):
+            gx = cx + ox; gy = cy + oy
+            px, py = pts[gy % period][gx % period]
+            d = math.hypot(gx + px - x, gy + py - y)
+            if d < d1: d1, d2 = d, d1
+            elif d < d2: d2 = d
+    return d1, d2
+
+def layer(name, seed):
+    """Height, albedo (linear rgb, normalized later) and roughness at (u, v)."""
+    if name == 'grass':
+        f = Fbm(16, 4, seed); blades = Fbm(64, 2, seed + 1, gain=0.6)
+        def sample(u, v):
+            h = 0.6 * blades(u, v) + 0.4 * f(u, v)
+            l = 0.3 + 0.45 * h
+            hue = f(u + 0.37, v + 0.11) - 0.5
+            return h, (l * (1 + 0.3 * hue), l, l * (1 - 0.3 * hue)), 0.85
+    elif name == 'rock':
+        f = Fbm(4, 5, seed, ridged=True); g = Fbm(8, 4, seed + 1); pts = cells(6, seed)
+        def sample(u, v):
+            d1, d2 = worley(pts, 6, u, v)
+            cracks = min((d2 - d1) * 4, 1)
+            h = 0.55 * f(u, v) + 0.25 * g(u, v) + 0.2 * cracks
+            l = 0.25 + 0.5 * h * (0.6 + 0.4 * cracks)
+            t = g(u + 0.5, v) - 0.5
+            return h, (l * (1 + 0.15 * t), l, l * (1 - 0.1 * t)), 0.65 + 0.25 * (1 - g(u, v))
+    elif name == 'dirt':
+        f = Fbm(8, 5, seed); pts = cells(24, seed)
+        def sample(u, v):
+            d1, _ = worley(pts, 24, u, v)
+            pebble = max(0, 1 - d1 * 2.5)
+            h = 0.6 * f(u, v) + 0.4 * pebble
+            l = 0.35 + 0.3 * f(u + 0.2, v + 0.7) + 0.2 * pebble
+            return h, (l, l, l), 0.95 - 0.3 * pebble
+    elif name == 'sand':
+        f = Fbm(8, 4, seed); grain = Fbm(128, 1, seed + 1)
+        def sample(u, v):
+            warp = f(u, v)
+            ripple = 0.5 + 0.5 * math.sin(2 * math.pi * (12 * v + 2 * math.sin(2 * math.pi * u) + 1.5 * warp))
+            h = 0.7 * ripple + 0.3 * grain(u, v)
+            l = 0.42 + 0.1 * ripple + 0.12 * grain(u, v)
+            return h, (l, l, l), 0.95
+    else:
+        f = Fbm(4, 5, seed); sparkle = Fbm(128, 1, seed + 1)
+        def sample(u, v):
+            h = f(u, v)
+            l = 0.47 + 0.06 * h + 0.04 * max(0, sparkle(u, v) - 0.7) / 0.3
+            return h, (l, l, l), 0.45 + 0.15 * h
+    return sample
+
+NORMAL_STRENGTH = {'grass': 2.0, 'rock': 6.0, 'dirt': 4.0, 'sand': 2.0, 'snow': 1.0}
+
+def to_srgb(c):
+    c = min(max(c, 0.0), 1.0)
+    return c * 12.92 if c <= 0.0031308 else 1.055 * c ** (1 / 2.4) - 0.055
+
+def from_srgb(c):
+    return c / 12.92 if c <= 0.04045 else ((c + 0.055) / 1.055) ** 2.4
+
+def u8(x):
+    return max(0, min(255, int(round(x * 255))))
+
+def downsample(img, size, channels, decode, encode):
+    half = size // 2
+    out = []
+    for y in range(half):
+        for x in range(half):
+            px = []
+            for c in range(channels):
+                s = sum(decode(img[((2 * y + dy) * size + 2 * x + dx) * channels + c] / 255, c)
+                        for dy in (0, 1) for dx in (0, 1)) / 4
+                px.append(u8(encode(s, c)))
+            out.extend(px)
+    return out
+
+def mips(img, channels, decode, encode):
+    levels = [img]; size = SIZE
+    while size > 1:
+        img = downsample(img, size, channels, decode, encode); size //= 2
+        levels.append(img)
+    return levels
+
+def dfd(channels, srgb):
+    samples = []
+    ids = [0, 1, 2, 15][:channels]
+    for i, cid in enumerate(ids):
+        ctype = cid | (0x10 if cid == 15 else 0)
+        samples.append(struct.pack('<HBB4BII', i * 8, 7, ctype, 0, 0, 0, 0, 0, 255))
+    block_size = 24 + 16 * len(samples)
+    block = struct.pack('<IHHBBBB4B8B', 0, 2, block_size, 1, 1, 2 if srgb else 1, 0,
+                        0, 0, 0, 0, channels, 0, 0, 0, 0, 0, 0, 0) + b''.join(samples)
+    return struct.pack('<I', 4 + len(block)) + block
+
+def write_ktx2(path, vk_format, channels, srgb, layers_levels):
+    level_count = len(layers_levels[0])
+    ident = bytes([0xAB, 0x4B, 0x54, 0x58, 0x20, 0x32, 0x30, 0xBB, 0x0D, 0x0A, 0x1A, 0x0A])
+    header_len = 12 + 9 * 4 + 4 * 4 + 2 * 8 + level_count * 24
+    d = dfd(channels, srgb)
+    dfd_offset = header_len
+    data_start = dfd_offset + len(d)
+    # Smallest levels first, as recommended
+    body = b''; offsets = [None] * level_count
+    pos = data_start
+    for level in reversed(range(level_count)):
+        pad = (-pos) % 4
+        body += b'\0' * pad; pos += pad
+        data = b''.join(bytes(layer[level]) for layer in layers_levels)
+        offsets[level] = (pos, len(data))
+        body += data; pos += len(data)
+    out = ident + struct.pack('<9I', vk_format, 1, SIZE, SIZE, 0, len(layers_levels), 1, level_count, 0)
+    out += struct.pack('<4I2Q', dfd_offset, len(d), 0, 0, 0, 0)
+    for off, length in offsets:
+        out += struct.pack('<3Q', off, length, length)
+    assert len(out) == header_len
+    out += d + body
+    with open(path, 'wb') as f:
+        f.write(out)
+
+def main():
+    os.makedirs(OUT, exist_ok=True)
+    albedo_layers, normal_layers, rough_layers = [], [], []
+    for i, name in enumerate(LAYERS):
+        sample = layer(name, 1000 + i)
+        heights = [0.0] * (SIZE * SIZE)
+        colors = []; rough = []
+        for y in range(SIZE):
+            for x in range(SIZE):
+                h, rgb, r = sample(x / SIZE, y / SIZE)
+                heights[y * SIZE + x] = h
+                colors.append(rgb)
+                rough.append(u8(r))
+        # Only the variation is up to the texture, the layer's color comes from the shading
+        scale = ALBEDO_MEAN / (sum(sum(rgb) for rgb in colors) / (3 * len(colors)))
+        albedo = []
+        for rgb in colors:
+            albedo.extend([u8(to_srgb(c * scale)) for c in rgb] + [255])
+        normal = []
+        k = NORMAL_STRENGTH[name]
+        for y in range(SIZE):
+            for x in range(SIZE):
+                dx = (heights[y * SIZE + (x + 1) % SIZE] - heights[y * SIZE + (x - 1) % SIZE]) * SIZE / 2 / SIZE * k * 4
+                dy = (heights[((y + 1) % SIZE) * SIZE + x] - heights[((y - 1) % SIZE) * SIZE + x]) * SIZE / 2 / SIZE * k * 4
+                n = (-dx, -dy, 1.0); l = math.sqrt(sum(c * c for c in n))
+                normal.extend([u8(n[0] / l * 0.5 + 0.5), u8(n[1] / l * 0.5 + 0.5)])
+        albedo_layers.append(mips(albedo, 4, lambda s, c: from_srgb(s) if c < 3 else s,
+                                  lambda s, c: to_srgb(s) if c < 3 else s))
+        normal_layers.append(mips(normal, 2, lambda s, c: s, lambda s, c: s))
+        rough_layers.append(mips(rough, 1, lambda s, c: s, lambda s, c: s))
+        print(name, 'done')
+    write_ktx2(f'{OUT}/terrain_albedo.ktx2', 43, 4, True, albedo_layers)
+    write_ktx2(f'{OUT}/terrain_normal.ktx2', 16, 2, False, normal_layers)
+    write_ktx2(f'{OUT}/terrain_roughness.ktx2', 9, 1, False, rough_layers)
+
+if __name__ == '__main__':
+    main()